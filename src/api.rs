@@ -1,158 +1,589 @@
-use std::collections::HashMap;
-
-use attohttpc::{MultipartBuilder, MultipartFile};
-use color_eyre::{
-    eyre::{eyre, Context},
-    Result,
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    io::{self, Write},
+    time::{Duration, Instant},
 };
-use log::debug;
+
+use attohttpc::{body::Body, RequestBuilder};
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 use url::Url;
 
-use crate::{Auth, Image, Response};
+use crate::{
+    doctor, Auth, Backend, Client, Content, DirectUpload, Field, Form, Image, ImageList,
+    ImageStats, Progress, RateLimiter, Response, Retry, ShotError, TokenStatus,
+};
+
+type Result<T, E = ShotError> = std::result::Result<T, E>;
 
+/// Blocking client of Cloudflare Images of one account.
+///
+/// Requests are retried according to [`Retry`], see [`API::with_retry`], and
+/// sent with HTTP settings of [`Client`], see [`API::with_client`].
 #[derive(Debug)]
 pub struct API {
-    account_id: String,
     token: String,
     session: attohttpc::Session,
-    api: Url,
+    urls: Urls,
+    retry: Retry,
+    limiter: RateLimiter,
 }
 
 impl API {
-    const API: &'static str = "https://api.cloudflare.com/client/v4/";
+    /// Default base URL of Cloudflare API
+    pub const API: &'static str = "https://api.cloudflare.com/client/v4/";
 
+    /// Client with default settings: 3 retries, no rate limit and proxies
+    /// from env vars
     pub fn new(auth: impl Into<Auth>) -> Result<Self> {
         let Auth { account_id, token } = auth.into();
         let this = Self {
-            api: Url::parse(Self::API)
-                .unwrap()
-                .join(&format!("accounts/{account_id}/images/v1"))
-                .wrap_err("Bad `account_id`")?,
-            account_id,
+            urls: Urls::new(Url::parse(Self::API).unwrap(), account_id)?,
             token,
             session: Default::default(),
+            retry: Default::default(),
+            limiter: RateLimiter::new(None),
         };
         Ok(this)
     }
 
-    pub fn verify_token(&self) -> Result<()> {
-        let url = Url::parse(Self::API)
-            .unwrap()
-            .join(&format!("accounts/{}/images/v1", self.account_id()))
-            .wrap_err("Bad `account_id`")?;
+    /// Use HTTP settings and API base URL of `client`
+    pub fn with_client(self, client: &Client) -> Result<Self> {
+        Ok(Self {
+            urls: Urls::new(client.api_base(), self.urls.account_id.clone())?,
+            session: client.session()?,
+            ..self
+        })
+    }
+
+    /// Retry and rate limit requests according to `retry`
+    pub fn with_retry(self, retry: Retry) -> Self {
+        Self {
+            limiter: RateLimiter::new(retry.rate_limit),
+            retry,
+            ..self
+        }
+    }
+
+    /// Check token status, expiry and Images permissions. Never fails, every
+    /// error is recorded in the report.
+    ///
+    /// Write permission can't be read from the token without permission to
+    /// read tokens, so it's checked by creating a direct upload draft, only
+    /// if `write` is set.
+    pub fn diagnose(&self, write: bool) -> doctor::Report {
+        let mut report = doctor::Report::default();
+
+        match self.verify() {
+            Ok(token) if token.status == "active" => {
+                let expiry = token
+                    .expires_on
+                    .map(|x| format!("expires on {x}"))
+                    .unwrap_or_else(|| "never expires".to_owned());
+                report.pass("Token", format!("active, {expiry}"))
+            }
+            Ok(token) => report.fail(
+                "Token",
+                format!("token is {}", token.status),
+                Some("Enable or roll the token in the dashboard".to_owned()),
+            ),
+            Err(e) => report.fail_error("Token", &e),
+        };
+
+        match self.list_page(1, 10) {
+            Ok(_) => report.pass("Images read", "allowed"),
+            Err(e) => report.warn_error("Images read", &e),
+        };
+
+        if !write {
+            report.warn(
+                "Images write",
+                "not checked",
+                Some("Checked without `--dry-run`, by creating a draft and deleting it".to_owned()),
+            );
+            return report;
+        }
+        // Creating a direct upload is the cheapest write. It's deleted right
+        // after, and expires by itself anyway.
+        match self.get_direct_upload() {
+            Ok(upload) => {
+                if let Err(e) = self.delete(&upload.id) {
+                    warn!(
+                        "Failed to delete draft {}, it expires by itself: {e}",
+                        upload.id
+                    )
+                }
+                report.pass("Images write", "allowed")
+            }
+            Err(e) => report.fail_error("Images write", &e),
+        };
+
+        report
+    }
+
+    /// Verify the token with Cloudflare. Tokens owned by an account are
+    /// verified by the account endpoint.
+    pub fn verify(&self) -> Result<TokenStatus> {
+        let [user, account] = self.urls.verify()?;
+        let err = match self.request(true, || Ok(self.session.get(&user))) {
+            Err(e @ ShotError::Api { .. }) => e,
+            res => return res,
+        };
+        self.request(true, || Ok(self.session.get(&account)))
+            .or(Err(err))
+    }
+
+    /// Time taken for the API base to respond. Any HTTP response counts, as
+    /// this only checks the network.
+    pub fn ping(&self) -> Result<Duration> {
+        let start = Instant::now();
+        self.session.head(&self.urls.base).send()?;
+        Ok(start.elapsed())
+    }
+
+    /// Number of images stored and allowed
+    pub fn stats(&self) -> Result<ImageStats> {
+        let url = self.urls.stats()?;
+        self.request(true, || Ok(self.session.get(&url)))
+    }
+
+    /// List images, `page` starts from 1
+    pub fn list_page(&self, page: u32, per_page: u32) -> Result<Value> {
+        self.request(true, || {
+            Ok(self
+                .session
+                .get(self.url())
+                .params(Urls::page(page, per_page)))
+        })
+    }
+
+    /// Details of image `id`
+    pub fn get(&self, id: &str) -> Result<Image> {
+        let url = self.urls.image(id)?;
+        self.request(true, || Ok(self.session.get(&url)))
+    }
+
+    /// Write the original file of image `id`, as uploaded, into `w`.
+    /// Returns bytes written.
+    pub fn blob(&self, id: &str, w: &mut dyn Write) -> Result<u64> {
+        let url = self.urls.blob(id)?;
+        let res = self.send(true, || Ok(self.session.get(&url)))?;
+        Self::copy(res, w)
+    }
+
+    /// Write image served at `url`, e.g. a variant, into `w`. Returns bytes
+    /// written.
+    pub fn download(&self, url: &Url, w: &mut dyn Write) -> Result<u64> {
         let res = self
-            .session
-            .get(url)
-            .bearer_auth(&self.token)
-            .send()
-            .wrap_err("Failed to request API")?;
-        let is_success = res.is_success();
-        let res_text = res.text().wrap_err("Bad response")?.replace('\n', "");
-        debug!("{}", res_text);
-        if is_success {
-            Ok(())
-        } else {
-            Err(eyre!("Response: {}", res_text).wrap_err("Unable to verify the auth pair"))
+            .retry
+            .send(&self.limiter, true, || Ok(self.session.get(url)))?;
+        Self::copy(res, w)
+    }
+
+    /// All images of the account, listed page by page
+    pub fn list_all(&self) -> Result<Vec<Image>> {
+        const PER_PAGE: u32 = 1000;
+        let mut images = vec![];
+        for page in 1.. {
+            let list = self.list(page, PER_PAGE)?;
+            let last = list.len() < PER_PAGE as usize;
+            images.extend(list);
+            if last {
+                break;
+            }
+        }
+        Ok(images)
+    }
+
+    /// Delete image `id`. Also deletes unused direct upload drafts.
+    pub fn delete(&self, id: &str) -> Result<()> {
+        let url = self.urls.image(id)?;
+        let res: Response<Value> = Self::parse(self.send(true, || Ok(self.session.delete(&url)))?)?;
+        res.into_option().map(drop)
+    }
+
+    /// Send request built by `req` with auth, retrying according to
+    /// [`Retry`]. `req` is called once per attempt.
+    fn send<B: Body>(
+        &self,
+        idempotent: bool,
+        req: impl Fn() -> Result<RequestBuilder<B>>,
+    ) -> Result<attohttpc::Response> {
+        self.retry.send(&self.limiter, idempotent, || {
+            Ok(req()?.bearer_auth(&self.token))
+        })
+    }
+
+    /// Send request and return `result` of the response
+    fn request<T: DeserializeOwned, B: Body>(
+        &self,
+        idempotent: bool,
+        req: impl Fn() -> Result<RequestBuilder<B>>,
+    ) -> Result<T> {
+        Self::parse(self.send(idempotent, req)?)?.into_result()
+    }
+
+    /// Copy body of a successful response into `w`
+    fn copy(res: attohttpc::Response, w: &mut dyn Write) -> Result<u64> {
+        if !res.is_success() {
+            let status = res.status().as_u16();
+            let body = res.text()?;
+            return Err(
+                match Response::<Value>::parse(status, body.clone())?.into_option() {
+                    Err(e) => e,
+                    Ok(_) => ShotError::Status { status, body },
+                },
+            );
         }
+        io::copy(&mut res.split().2, w).map_err(|e| ShotError::Network(e.into()))
+    }
+
+    fn parse<T: DeserializeOwned>(res: attohttpc::Response) -> Result<Response<T>> {
+        let status = res.status().as_u16();
+        Response::parse(status, res.text()?)
+    }
+
+    /// Base URL of Cloudflare API
+    pub fn base(&self) -> &Url {
+        &self.urls.base
     }
 
     /// Get the Cloudflare Image API url
     pub fn url(&self) -> &Url {
-        &self.api
+        &self.urls.images
     }
 
     /// Get the Cloudflare Account ID
     pub fn account_id(&self) -> &str {
-        &self.account_id
+        &self.urls.account_id
     }
 
-    pub fn get_direct_upload(&self) -> Result<()> {
-        todo!()
+    /// Create a one-time URL that images can be uploaded to without token
+    pub fn get_direct_upload(&self) -> Result<DirectUpload> {
+        let url = self.urls.direct_upload()?;
+        let fields = Field::direct_upload();
+        self.request(false, || {
+            Ok(self.session.post(&url).body(Form::new(&fields, None)))
+        })
     }
 
+    /// Start building an upload of encoded image `bytes`
     pub fn upload<'a>(&'a self, filename: &'a str, bytes: &'a [u8]) -> Upload<'a> {
-        Upload::new(self, filename, bytes)
+        Upload::new(self, filename, Content::Bytes(bytes))
+    }
+
+    /// Start building an upload of `content`, which may be streamed from a
+    /// file
+    pub fn upload_content<'a>(&'a self, filename: &'a str, content: Content<'a>) -> Upload<'a> {
+        Upload::new(self, filename, content)
     }
 }
 
-/// Upload builder. Use [`send`] to perform the upload.
-pub struct Upload<'a> {
-    api: &'a API,
-    filename: &'a str,
-    bytes: &'a [u8],
-    require_signed_urls: bool,
-    meta: HashMap<&'a str, &'a str>,
+/// URLs of an account, shared by the blocking and async clients
+#[derive(Debug, Clone)]
+pub(crate) struct Urls {
+    pub account_id: String,
+    pub base: Url,
+    pub images: Url,
+}
+
+impl Urls {
+    pub fn new(base: Url, account_id: String) -> Result<Self> {
+        let images = base
+            .join(&format!("accounts/{account_id}/images/v1"))
+            .map_err(|e| ShotError::Config(format!("Bad `account_id` `{account_id}`: {e}")))?;
+        Ok(Self {
+            account_id,
+            base,
+            images,
+        })
+    }
+
+    /// Url of `path` relative to API root
+    pub fn endpoint(&self, path: &str) -> Result<Url> {
+        self.base
+            .join(path)
+            .map_err(|e| ShotError::Config(format!("Bad API path `{path}`: {e}")))
+    }
+
+    /// Url of a single image
+    pub fn image(&self, id: &str) -> Result<Url> {
+        let mut url = self.images.clone();
+        url.path_segments_mut()
+            .map_err(|_| ShotError::Config(format!("Bad API url `{}`", self.images)))?
+            .push(id);
+        Ok(url)
+    }
+
+    /// Token verify endpoints, of user tokens and account tokens
+    pub fn verify(&self) -> Result<[Url; 2]> {
+        Ok([
+            self.endpoint("user/tokens/verify")?,
+            self.endpoint(&format!("accounts/{}/tokens/verify", self.account_id))?,
+        ])
+    }
+
+    /// Url of the original file of an image
+    pub fn blob(&self, id: &str) -> Result<Url> {
+        let mut url = self.image(id)?;
+        // Cannot-be-a-base URLs are rejected by `image`
+        url.path_segments_mut().unwrap().push("blob");
+        Ok(url)
+    }
+
+    pub fn stats(&self) -> Result<Url> {
+        self.endpoint(&format!("accounts/{}/images/v1/stats", self.account_id))
+    }
+
+    pub fn direct_upload(&self) -> Result<Url> {
+        self.endpoint(&format!(
+            "accounts/{}/images/v2/direct_upload",
+            self.account_id
+        ))
+    }
+
+    /// Query of a list page
+    pub fn page(page: u32, per_page: u32) -> [(&'static str, String); 2] {
+        [
+            ("page", page.to_string()),
+            ("per_page", per_page.to_string()),
+        ]
+    }
+}
+
+/// Upload builder. Use `send` to perform the upload, which is async if
+/// created by [`AsyncAPI`](crate::AsyncAPI).
+///
+/// Works with any [`Backend`], e.g. `Upload::new(&local, "a.png", content)`.
+pub struct Upload<'a, C: ?Sized = API> {
+    pub(crate) api: &'a C,
+    pub(crate) draft: Draft<'a>,
 }
 
 impl<'a> Upload<'a> {
-    fn new(api: &'a API, filename: &'a str, bytes: &'a [u8]) -> Self {
+    /// Maximum size of serialized metadata
+    pub const META_LIMIT: usize = 1024;
+    /// Maximum size of image, see
+    /// <https://developers.cloudflare.com/images/cloudflare-images/upload-images/formats-limitations/>
+    pub const IMAGE_LIMIT: usize = 10_000_000;
+}
+
+impl<'a, C: ?Sized> Upload<'a, C> {
+    pub fn new(api: &'a C, filename: &'a str, content: Content<'a>) -> Self {
         Self {
             api,
-            filename,
-            bytes,
-            require_signed_urls: Default::default(),
-            meta: Default::default(),
+            draft: Draft {
+                filename,
+                id: None,
+                content,
+                content_type: "image/png",
+                require_signed_urls: Default::default(),
+                meta: Default::default(),
+                progress: None,
+            },
         }
     }
 
+    /// Replace image content
     pub fn bytes(self, bytes: &'a [u8]) -> Self {
-        Self { bytes, ..self }
+        self.content(Content::Bytes(bytes))
     }
 
-    pub fn filename(self, filename: &'a str) -> Self {
-        Self { filename, ..self }
+    /// Replace image content, e.g. with a file to be streamed
+    pub fn content(mut self, content: Content<'a>) -> Self {
+        self.draft.content = content;
+        self
     }
 
-    pub fn require_signed_urls(self) -> Self {
-        Self {
-            require_signed_urls: true,
-            ..self
-        }
+    /// MIME type of the content [default: image/png]
+    pub fn content_type(mut self, content_type: &'a str) -> Self {
+        self.draft.content_type = content_type;
+        self
     }
 
-    pub fn add_meta(&mut self, key: &'a str, value: &'a str) -> &mut Self {
-        self.meta.insert(key, value);
+    /// Replace file name, which Cloudflare stores as `filename`
+    pub fn filename(mut self, filename: &'a str) -> Self {
+        self.draft.filename = filename;
         self
     }
 
-    pub fn extend_meta(&mut self, meta: impl Iterator<Item = (&'a str, &'a str)>) -> &mut Self {
-        self.meta.extend(meta);
+    /// Use a custom ID instead of the one generated by the backend
+    pub fn id(mut self, id: &'a str) -> Self {
+        self.draft.id = Some(id);
         self
     }
 
-    pub fn send(self) -> Result<Response<Image>> {
-        let url = self.api.url();
+    /// Only serve the image with signed URLs
+    pub fn require_signed_urls(mut self) -> Self {
+        self.draft.require_signed_urls = true;
+        self
+    }
 
-        debug!("API Url: {}", url);
+    /// Report bytes sent while uploading. Not supported by the async client.
+    pub fn progress(mut self, progress: Progress<'a>) -> Self {
+        self.draft.progress = Some(progress);
+        self
+    }
 
-        let file = MultipartFile::new("file", self.bytes)
-            .with_filename(self.filename)
-            .with_type("image/png")?;
+    /// Add a metadata entry, replacing any with the same key
+    pub fn add_meta(&mut self, key: &'a str, value: impl Into<Value>) -> &mut Self {
+        self.draft.meta.insert(key, value.into());
+        self
+    }
 
-        let signed = self.require_signed_urls.to_string();
-        let meta = serde_json::to_string(&self.meta).wrap_err("Failed to serialize metadata")?;
+    /// Add metadata entries, replacing any with the same keys
+    pub fn extend_meta<V: Into<Value>>(
+        &mut self,
+        meta: impl Iterator<Item = (&'a str, V)>,
+    ) -> &mut Self {
+        self.draft.meta.extend(meta.map(|(k, v)| (k, v.into())));
+        self
+    }
 
-        let form = MultipartBuilder::new()
-            .with_file(file)
-            .with_text("requireSignedURLs", &signed)
-            .with_text("metadata", &meta)
-            .build()?;
+    pub fn draft(&self) -> &Draft<'a> {
+        &self.draft
+    }
+}
 
-        let res = self
-            .api
-            .session
-            .post(url)
-            .body(form)
-            .bearer_auth(&self.api.token)
-            .send()
-            .wrap_err("Failed to request API")?
-            .error_for_status()
-            .wrap_err("Failed to request API")?
-            .json()
-            .wrap_err("Failed to parse response json")?;
-
-        debug!("Res: {:#?}", res);
-
-        Ok(res)
+impl<'a, B: Backend + ?Sized> Upload<'a, B> {
+    /// Check the upload against limits of the backend without sending it
+    pub fn validate(&self) -> Result<()> {
+        self.api.validate(&self.draft)
+    }
+
+    pub fn send(self) -> Result<Image> {
+        self.api.upload(&self.draft)
+    }
+}
+
+/// Content and attributes of an image to be uploaded, built by [`Upload`]
+/// and read by backends
+pub struct Draft<'a> {
+    pub filename: &'a str,
+    pub id: Option<&'a str>,
+    pub content: Content<'a>,
+    pub content_type: &'a str,
+    pub require_signed_urls: bool,
+    pub meta: HashMap<&'a str, Value>,
+    pub progress: Option<Progress<'a>>,
+}
+
+impl<'a> Draft<'a> {
+    pub fn meta_json(&self) -> String {
+        // Map of JSON values is always serializable
+        serde_json::to_string(&self.meta).unwrap()
+    }
+
+    /// Check against Cloudflare limits. Cloudflare rejects metadata larger
+    /// than 1024 bytes, and custom IDs of images requiring signed URLs, so
+    /// they are checked here instead of waiting for the server.
+    pub(crate) fn check_limits(&self) -> Result<()> {
+        if let Some(id) = self.id.filter(|_| self.require_signed_urls) {
+            return Err(ShotError::Config(format!(
+                "Cloudflare Images doesn't allow custom ID `{id}` on an image requiring signed \
+                 URLs"
+            )));
+        }
+        let size = self.content.len().try_into().unwrap_or(usize::MAX);
+        if size > Upload::IMAGE_LIMIT {
+            return Err(ShotError::TooLarge {
+                what: "Image",
+                size,
+                limit: Upload::IMAGE_LIMIT,
+            });
+        }
+        let meta = self.meta_json();
+        debug!("Metadata: {meta}");
+        if meta.len() > Upload::META_LIMIT {
+            return Err(ShotError::TooLarge {
+                what: "Serialized metadata",
+                size: meta.len(),
+                limit: Upload::META_LIMIT,
+            });
+        }
+        Ok(())
+    }
+
+    /// Validated form fields of Cloudflare upload
+    pub(crate) fn fields(&self) -> Result<Vec<Field<'_>>> {
+        self.check_limits()?;
+        let mut fields = vec![
+            Field::File {
+                name: "file",
+                filename: self.filename,
+                content_type: self.content_type,
+                content: &self.content,
+            },
+            Field::Text("requireSignedURLs", self.require_signed_urls.to_string()),
+            Field::Text("metadata", self.meta_json()),
+        ];
+        if let Some(id) = self.id {
+            fields.push(Field::Text("id", id.to_owned()));
+        }
+        Ok(fields)
+    }
+
+    /// ID of the image to fetch instead of failing, if a retried upload
+    /// failed because the image exists. The first attempt may have succeeded
+    /// before the connection dropped.
+    pub(crate) fn uploaded_id(&self, attempts: u32, err: &ShotError) -> Option<&'a str> {
+        self.id
+            .filter(|_| attempts > 1 && err.codes().contains(&5409))
+    }
+}
+
+impl Backend for API {
+    fn validate(&self, draft: &Draft) -> Result<()> {
+        draft.check_limits()
+    }
+
+    fn size_limit(&self) -> Option<usize> {
+        Some(Upload::IMAGE_LIMIT)
+    }
+
+    /// Upload the image. With a custom ID, uploading is idempotent and is
+    /// retried on any transient error. Without one, a retry could create a
+    /// duplicate, so it's only retried when the request is surely not
+    /// received.
+    fn upload(&self, draft: &Draft) -> Result<Image> {
+        debug!("API Url: {}", self.url());
+
+        let fields = draft.fields()?;
+        let attempts = Cell::new(0);
+
+        let res = self.send(draft.id.is_some(), || {
+            attempts.set(attempts.get() + 1);
+            let form = Form::new(&fields, draft.progress);
+            Ok(self.session.post(self.url()).body(form))
+        })?;
+
+        match API::parse(res)?.into_result() {
+            Err(e) => match draft.uploaded_id(attempts.get(), &e) {
+                Some(id) => {
+                    debug!("Image `{id}` exists after retrying, fetching it");
+                    self.get(id)
+                }
+                None => Err(e),
+            },
+            res => res,
+        }
+    }
+
+    fn get(&self, id: &str) -> Result<Image> {
+        API::get(self, id)
+    }
+
+    fn list(&self, page: u32, per_page: u32) -> Result<Vec<Image>> {
+        let url = self.url();
+        let list: ImageList = self.request(true, || {
+            Ok(self.session.get(url).params(Urls::page(page, per_page)))
+        })?;
+        Ok(list.images)
+    }
+
+    fn delete(&self, id: &str) -> Result<()> {
+        API::delete(self, id)
     }
 }
@@ -0,0 +1,258 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use log::debug;
+use reqwest::{
+    multipart::{Form, Part},
+    RequestBuilder,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    api::Urls, Auth, Client, Content, DirectUpload, Field, Image, RateLimiter, Response, Retry,
+    ShotError, Upload, API,
+};
+
+type Result<T, E = ShotError> = std::result::Result<T, E>;
+
+/// Async client of Cloudflare Images of one account, on tokio.
+///
+/// Same as [`API`], sharing its models, URLs, forms and retry logic. Uploads
+/// are built with the same [`Upload`] builder, whose `send` is async here.
+///
+/// ```no_run
+/// # async fn run(bytes: Vec<u8>) -> Result<(), shot::ShotError> {
+/// use shot::{AsyncAPI, Auth};
+///
+/// let api = AsyncAPI::new(Auth {
+///     account_id: "account id".to_owned(),
+///     token: "api token".to_owned(),
+/// })?;
+/// let image = api.upload("a.png", &bytes).send().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AsyncAPI {
+    token: String,
+    client: reqwest::Client,
+    urls: Urls,
+    retry: Retry,
+    limiter: RateLimiter,
+}
+
+impl AsyncAPI {
+    /// Client with default settings: 3 retries, no rate limit and proxies
+    /// from env vars
+    pub fn new(auth: impl Into<Auth>) -> Result<Self> {
+        let Auth { account_id, token } = auth.into();
+        Ok(Self {
+            urls: Urls::new(Url::parse(API::API).unwrap(), account_id)?,
+            token,
+            client: Default::default(),
+            retry: Default::default(),
+            limiter: RateLimiter::new(None),
+        })
+    }
+
+    /// Use HTTP settings and API base URL of `client`
+    pub fn with_client(self, client: &Client) -> Result<Self> {
+        Ok(Self {
+            urls: Urls::new(client.api_base(), self.urls.account_id.clone())?,
+            client: client.async_client()?,
+            ..self
+        })
+    }
+
+    /// Retry and rate limit requests according to `retry`
+    pub fn with_retry(self, retry: Retry) -> Self {
+        Self {
+            limiter: RateLimiter::new(retry.rate_limit),
+            retry,
+            ..self
+        }
+    }
+
+    /// List images, `page` starts from 1
+    pub async fn list_page(&self, page: u32, per_page: u32) -> Result<Value> {
+        let query = Urls::page(page, per_page);
+        self.request(true, || {
+            Ok(self.client.get(self.url().clone()).query(&query))
+        })
+        .await
+    }
+
+    /// Details of image `id`
+    pub async fn get(&self, id: &str) -> Result<Image> {
+        let url = self.urls.image(id)?;
+        self.request(true, || Ok(self.client.get(url.clone())))
+            .await
+    }
+
+    /// Delete image `id`. Also deletes unused direct upload drafts.
+    pub async fn delete(&self, id: &str) -> Result<()> {
+        let url = self.urls.image(id)?;
+        let res = self
+            .send(true, || Ok(self.client.delete(url.clone())))
+            .await?;
+        Self::parse::<Value>(res).await?.into_option().map(drop)
+    }
+
+    /// Create a one-time URL that images can be uploaded to without token
+    pub async fn get_direct_upload(&self) -> Result<DirectUpload> {
+        let url = self.urls.direct_upload()?;
+        let fields = Field::direct_upload();
+        self.request(false, || {
+            Ok(self
+                .client
+                .post(url.clone())
+                .multipart(multipart(&fields, &[])?))
+        })
+        .await
+    }
+
+    /// Start building an upload of encoded image `bytes`
+    pub fn upload<'a>(&'a self, filename: &'a str, bytes: &'a [u8]) -> Upload<'a, Self> {
+        Upload::new(self, filename, Content::Bytes(bytes))
+    }
+
+    /// Start building an upload of `content`. Content of readers is read
+    /// into memory once before sending, as they are blocking.
+    pub fn upload_content<'a>(
+        &'a self,
+        filename: &'a str,
+        content: Content<'a>,
+    ) -> Upload<'a, Self> {
+        Upload::new(self, filename, content)
+    }
+
+    /// Base URL of Cloudflare API
+    pub fn base(&self) -> &Url {
+        &self.urls.base
+    }
+
+    /// Get the Cloudflare Image API url
+    pub fn url(&self) -> &Url {
+        &self.urls.images
+    }
+
+    /// Get the Cloudflare Account ID
+    pub fn account_id(&self) -> &str {
+        &self.urls.account_id
+    }
+
+    /// Send request built by `req` with auth, retrying according to
+    /// [`Retry`]. `req` is called once per attempt.
+    async fn send(
+        &self,
+        idempotent: bool,
+        req: impl Fn() -> Result<RequestBuilder>,
+    ) -> Result<reqwest::Response> {
+        let mut attempt = 0;
+        loop {
+            if let Some(delay) = self.limiter.reserve() {
+                tokio::time::sleep(delay).await;
+            }
+            let outcome = req()?.bearer_auth(&self.token).send().await;
+            match self.retry.delay(attempt, &(&outcome).into(), idempotent) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Ok(outcome?),
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Send request and return `result` of the response
+    async fn request<T: DeserializeOwned>(
+        &self,
+        idempotent: bool,
+        req: impl Fn() -> Result<RequestBuilder>,
+    ) -> Result<T> {
+        Self::parse(self.send(idempotent, req).await?)
+            .await?
+            .into_result()
+    }
+
+    async fn parse<T: DeserializeOwned>(res: reqwest::Response) -> Result<Response<T>> {
+        let status = res.status().as_u16();
+        Response::parse(status, res.text().await?)
+    }
+}
+
+/// Content of the file field of `fields`, read once before any attempt.
+/// Readers are read on the current thread, as they borrow from the caller.
+fn read_file(fields: &[Field]) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    for field in fields {
+        if let Field::File { content, .. } = field {
+            bytes.reserve(content.len().try_into().unwrap_or(0));
+            content
+                .write_to(&mut bytes)
+                .map_err(|e| ShotError::Network(e.into()))?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// reqwest forms own their content, so each attempt copies `file`, the
+/// content read by [`read_file`]
+fn multipart(fields: &[Field], file: &[u8]) -> Result<Form> {
+    fields
+        .iter()
+        .try_fold(Form::new(), |form, field| match *field {
+            Field::Text(name, ref text) => Ok(form.text(name.to_owned(), text.clone())),
+            Field::File {
+                name,
+                filename,
+                content_type,
+                ..
+            } => {
+                let part = Part::bytes(file.to_vec())
+                    .file_name(filename.to_owned())
+                    .mime_str(content_type)?;
+                Ok(form.part(name.to_owned(), part))
+            }
+        })
+}
+
+impl<'a> Upload<'a, AsyncAPI> {
+    /// Check the upload against Cloudflare limits without sending it
+    pub fn validate(&self) -> Result<()> {
+        self.draft.check_limits()
+    }
+
+    /// Upload the image, retried the same way as [`Upload::send`]. Unlike
+    /// the streaming upload of [`API`], the whole content is buffered in
+    /// memory, including files given as [`Content::Reader`].
+    pub async fn send(self) -> Result<Image> {
+        debug!("API Url: {}", self.api.url());
+
+        let fields = self.draft.fields()?;
+        let file = read_file(&fields)?;
+        let attempts = AtomicU32::new(0);
+
+        let res = self
+            .api
+            .send(self.draft.id.is_some(), || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Ok(self
+                    .api
+                    .client
+                    .post(self.api.url().clone())
+                    .multipart(multipart(&fields, &file)?))
+            })
+            .await?;
+
+        match AsyncAPI::parse(res).await?.into_result() {
+            Err(e) => match self.draft.uploaded_id(attempts.into_inner(), &e) {
+                Some(id) => {
+                    debug!("Image `{id}` exists after retrying, fetching it");
+                    self.api.get(id).await
+                }
+                None => Err(e),
+            },
+            res => res,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::{fmt, path::PathBuf};
+
+use serde::{Deserialize as De, Serialize as Ser};
+use url::Url;
+
+use crate::{CustomUploader, Draft, Image, S3Config, ShotError};
+
+type Result<T, E = ShotError> = std::result::Result<T, E>;
+
+/// Where images are stored. Implemented by [`API`](crate::API) for
+/// Cloudflare Images, [`Local`](crate::Local), [`S3`](crate::S3) and
+/// [`Custom`](crate::Custom).
+///
+/// Uploads are built with [`Upload::new`](crate::Upload::new) and sent by
+/// the backend. Backends that can't get, list or delete images return
+/// [`ShotError::Unsupported`].
+pub trait Backend {
+    /// Check `draft` against limits of the backend without uploading
+    fn validate(&self, _draft: &Draft) -> Result<()> {
+        Ok(())
+    }
+
+    /// Largest image accepted, in bytes. Larger images are downsized before
+    /// uploading.
+    fn size_limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// Check that images uploaded with a custom ID can be found by it, for
+    /// [`Import`](crate::Import) to skip images already uploaded
+    fn check_ids_kept(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn upload(&self, draft: &Draft) -> Result<Image>;
+
+    fn get(&self, id: &str) -> Result<Image>;
+
+    /// List images, `page` starts from 1
+    fn list(&self, page: u32, per_page: u32) -> Result<Vec<Image>>;
+
+    fn delete(&self, id: &str) -> Result<()>;
+}
+
+/// Backend of a profile, see [`Config::backend`](crate::Config::backend)
+///
+/// ```ron
+/// backend: Local(dir: "/srv/images", base_url: "https://img.example.com/"),
+/// ```
+#[derive(Ser, De, Debug, Clone, Default)]
+pub enum BackendConfig {
+    /// Cloudflare Images, with `auth` of the profile
+    #[default]
+    Cloudflare,
+    /// Files in a local directory, served from `base_url` by a web server
+    Local { dir: PathBuf, base_url: Url },
+    /// S3 compatible bucket, e.g. R2 or MinIO
+    S3(S3Config),
+    /// Any HTTP endpoint, described like a ShareX custom uploader
+    Custom(CustomUploader),
+}
+
+impl BackendConfig {
+    pub fn is_cloudflare(&self) -> bool {
+        matches!(self, BackendConfig::Cloudflare)
+    }
+}
+
+/// Short description without credentials, e.g. for `shot doctor`
+impl fmt::Display for BackendConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendConfig::Cloudflare => write!(f, "Cloudflare Images"),
+            BackendConfig::Local { dir, base_url } => {
+                write!(f, "{} served from {base_url}", dir.display())
+            }
+            BackendConfig::S3(s3) => write!(f, "bucket {} at {}", s3.bucket, s3.endpoint),
+            BackendConfig::Custom(custom) => match custom.name {
+                Some(ref name) => write!(f, "custom uploader {name}"),
+                None => write!(f, "custom uploader of {}", custom.url),
+            },
+        }
+    }
+}
+
+/// Error of operations a backend doesn't support
+pub(crate) fn unsupported(backend: &str, op: &str) -> ShotError {
+    ShotError::Unsupported {
+        backend: backend.to_owned(),
+        op: op.to_owned(),
+    }
+}
@@ -1,22 +1,34 @@
-use std::{io::Cursor, path::PathBuf, str::FromStr};
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, Cursor, Read},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+};
 
 use arboard::Clipboard;
-use clap::{ColorChoice, Parser, Subcommand};
+use clap::{ColorChoice, Parser, Subcommand, ValueEnum};
 use color_eyre::{
-    eyre::{Context, ContextCompat},
+    eyre::{eyre, Context, ContextCompat},
     owo_colors::OwoColorize,
     Result,
 };
-use home::home_dir;
 use image::{imageops::FilterType, io::Reader, GenericImageView};
-use log::{debug, error, info};
-use tap::Pipe;
-
-use crate::{image_data_to_png, image_name, Auth, Config};
+use log::{debug, info, warn};
+use serde_json::{Map, Value};
+
+use shot::{
+    doctor::Report, image_data_to_image, image_name, mime_type, Auth, BackendConfig, Client,
+    Config, Content, CustomUploader, Export, Fault, Import, ImportSource, ImportSummary, Manifest,
+    ManifestFormat, MockServer, Output, Placeholder, Process, Profile, ProjectConfig, ReadSeek,
+    Retry, ShotError, Source, Upload, API,
+};
 
-pub const CONFIG_PATH: &str = ".config/shot.ron";
+use crate::progress::ProgressBar;
 
-pub const BIN_NAME: &str = clap::crate_name!();
+/// Name of the binary, used in hints
+const BIN_NAME: &str = env!("CARGO_PKG_NAME");
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, color = ColorChoice::Always)]
@@ -30,9 +42,34 @@ pub struct Opt {
 
 #[derive(Parser, Debug)]
 pub struct Flag {
-    #[clap(short, long)]
+    #[clap(short, long, global = true)]
     /// Preview the command without perform any actions
     dry_run: bool,
+
+    #[clap(short, long, value_enum, global = true)]
+    /// Format of the result printed after upload [default: human]
+    output: Option<Output>,
+
+    #[clap(short, long, global = true, env = "SHOT_PROFILE")]
+    /// Profile in config to use [default: `default_profile` in config, or
+    /// `default`]
+    profile: Option<String>,
+
+    #[clap(long, global = true)]
+    /// Only show this variant after upload, e.g. `public`. With `export`,
+    /// download this variant instead of original files
+    variant: Option<String>,
+
+    #[clap(short, long, global = true, env = "SHOT_CONFIG", value_name = "PATH")]
+    /// Config file to use [default: `$XDG_CONFIG_HOME/shot.ron`, or
+    /// `~/.config/shot.ron`]
+    config: Option<PathBuf>,
+
+    #[clap(flatten)]
+    retry: Retry,
+
+    #[clap(flatten)]
+    client: Client,
 }
 
 #[derive(Subcommand, Debug)]
@@ -44,6 +81,14 @@ pub enum Cmd {
         #[clap(flatten)]
         auth: Auth,
     },
+    /// Check config, auth, network, quota, clipboard and supported image
+    /// formats, and print a report
+    Doctor,
+    /// View and edit config
+    Config {
+        #[clap(subcommand)]
+        cmd: ConfigCmd,
+    },
     /// Upload image in clipboard to Cloudflare Image
     Paste {
         #[clap(short = 'n', long)]
@@ -51,34 +96,235 @@ pub enum Cmd {
         /// (e.g. 2021-12-20T01:01:01Z.png)
         file_name: Option<String>,
 
+        #[clap(long)]
+        /// Custom ID of the image, instead of one generated by Cloudflare
+        id: Option<String>,
+
         #[clap(short, long)]
         /// User modifyable key-value store that binds to image. Takes multiple
-        /// value Format: $KEY=$VALUE
+        /// value Format: $KEY=$VALUE, or $KEY:=$JSON for typed values
         metadata: Vec<KV>,
+
+        #[clap(long, value_name = "FILE")]
+        /// JSON or RON file of metadata, overridden by `--metadata`
+        metadata_file: Option<PathBuf>,
+
+        #[clap(flatten)]
+        process: Process,
     },
     /// Encode local images to PNG and upload to Cloudflare Images.
     /// For all supported image format,
     /// see `https://docs.rs/image/latest/image/codecs/index.html#supported-formats`.
     Upload {
-        /// Path of image to be uploaded
+        /// Path of image to be uploaded, or `-` for stdin
         file_path: PathBuf,
 
         #[clap(short = 'n', long)]
         /// Filename of the image, default to local file name
         file_name: Option<String>,
 
+        #[clap(long)]
+        /// Custom ID of the image, instead of one generated by Cloudflare
+        id: Option<String>,
+
         #[clap(short, long)]
         /// User modifyable key-value store that binds to image. Takes multiple
-        /// value Format: $KEY=$VALUE
+        /// value Format: $KEY=$VALUE, or $KEY:=$JSON for typed values
         metadata: Vec<KV>,
+
+        #[clap(long, value_name = "FILE")]
+        /// JSON or RON file of metadata, overridden by `--metadata`
+        metadata_file: Option<PathBuf>,
+
+        #[clap(long, value_enum, default_value_t)]
+        /// Format of the uploaded image
+        format: Format,
+
+        #[clap(flatten)]
+        process: Process,
+    },
+    /// Download every image of the account into DIR, with a manifest of
+    /// their IDs, file names, metadata and upload time. Running it again
+    /// resumes, skipping files already downloaded
+    Export {
+        /// Directory to export into, created if missing
+        dir: PathBuf,
+
+        #[clap(long, value_enum, default_value_t)]
+        /// Format of the manifest
+        manifest: ManifestFormat,
+
+        #[clap(short, long, default_value_t = 4)]
+        /// Number of parallel downloads
+        jobs: usize,
     },
+    /// Upload images exported by `export` with their IDs, file names,
+    /// metadata and signed URL requirement. Images whose ID already exists
+    /// are skipped, so it can be run again to retry
+    Import {
+        /// Export directory, or its manifest file
+        path: PathBuf,
+    },
+    /// Copy every image of the account of a profile into another, with their
+    /// IDs, file names, metadata and signed URL requirement. Images whose ID
+    /// already exists in the destination are skipped
+    Migrate {
+        #[clap(long, value_name = "PROFILE")]
+        /// Profile of the Cloudflare account to copy from
+        from: String,
+
+        #[clap(long, value_name = "PROFILE")]
+        /// Profile to copy into, of any backend
+        to: String,
+    },
+    /// Serve a mock of Cloudflare Images API, for development and tests
+    /// without an account. Use it with `--api-base`
+    MockServer {
+        #[clap(long, default_value = "127.0.0.1:8787")]
+        /// Address to listen on
+        addr: String,
+
+        #[clap(long, value_name = "DIR")]
+        /// Keep images in this directory across restarts [default: in memory]
+        dir: Option<PathBuf>,
+
+        #[clap(long, default_value = MockServer::TOKEN)]
+        /// Token to accept
+        token: String,
+
+        #[clap(long = "fail", value_name = "STATUS[xN]")]
+        /// Respond the first N requests with STATUS, e.g. `401`, `413`,
+        /// `429` or `503x2`. Takes multiple values, injected in order
+        faults: Vec<Fault>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default)]
+pub enum Format {
+    /// Decode, process and re-encode into PNG
+    #[default]
+    Png,
+    /// Upload the file as is, streamed without reading it into memory. Image
+    /// processing options are ignored
+    Original,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCmd {
+    /// Print value of a key, e.g. `profiles.default.variant`
+    Get { key: String },
+    /// Set value of a key. Value is parsed as JSON, or taken as a string if
+    /// that fails
+    Set { key: String, value: String },
+    /// Reset a key to default, or remove an entry from a map like `profiles`
+    Unset { key: String },
+    /// Print all keys and values, with tokens redacted
+    List,
+    /// Print path of config file
+    Path,
+    /// Open config file in `$VISUAL` or `$EDITOR`, and validate it afterwards
+    Edit,
+    /// Import a ShareX custom uploader (.sxcu) as backend of the profile
+    /// named by `--profile`, or by the file name
+    Import { file: PathBuf },
+}
+
+impl ConfigCmd {
+    fn handle(self, path: &Path, profile: Option<&str>, dry_run: bool) -> Result<()> {
+        let mut config = Config::from_dir_or_default(path)?;
+        match self {
+            ConfigCmd::Get { key } => match config.get(&key)? {
+                Value::String(s) => println!("{s}"),
+                value => println!("{}", serde_json::to_string_pretty(&value)?),
+            },
+            ConfigCmd::List => config
+                .list()?
+                .into_iter()
+                .for_each(|(k, v)| println!("{k} = {v}")),
+            ConfigCmd::Path => println!("{}", path.display()),
+            ConfigCmd::Set { key, value } => {
+                let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+                config.set(&key, value)?;
+                if dry_run {
+                    info!("with --dry-run, furthur actions are avoided.");
+                    return Ok(());
+                }
+                config.write_to(path)?;
+            }
+            ConfigCmd::Unset { key } => {
+                config.unset(&key)?;
+                if dry_run {
+                    info!("with --dry-run, furthur actions are avoided.");
+                    return Ok(());
+                }
+                config.write_to(path)?;
+            }
+            ConfigCmd::Import { file } => {
+                let content = fs::read_to_string(&file)
+                    .wrap_err_with(|| format!("Unable to read {}", file.display()))?;
+                let uploader = CustomUploader::from_sxcu(&content)?;
+                let name = profile
+                    .or_else(|| file.file_stem().and_then(|x| x.to_str()))
+                    .unwrap_or(Config::DEFAULT_PROFILE)
+                    .to_owned();
+                if dry_run {
+                    let backend = BackendConfig::Custom(uploader);
+                    println!(
+                        "{}",
+                        ron::ser::to_string_pretty(&backend, Default::default())?
+                    );
+                    info!("with --dry-run, furthur actions are avoided.");
+                    return Ok(());
+                }
+                config.set_backend(&name, BackendConfig::Custom(uploader));
+                config.write_to(path)?;
+                info!("Imported as profile `{name}`, use it with `--profile {name}`");
+            }
+            ConfigCmd::Edit => {
+                // Edit a copy, so an invalid edit never replaces a working
+                // config
+                let content = match fs::read(path) {
+                    Ok(content) => content,
+                    Err(_) => ron::ser::to_string_pretty(&config, Default::default())?.into_bytes(),
+                };
+                let tmp = env::temp_dir().join(format!("{BIN_NAME}-{}.ron", process::id()));
+                Config::write_raw(&tmp, &content)?;
+
+                let editor = env::var("VISUAL")
+                    .or_else(|_| env::var("EDITOR"))
+                    .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_owned());
+                let mut args = editor.split_whitespace();
+                let status = process::Command::new(args.next().wrap_err("Empty editor")?)
+                    .args(args)
+                    .arg(&tmp)
+                    .status()
+                    .wrap_err_with(|| format!("Failed to run editor `{editor}`"))?;
+                if !status.success() {
+                    return Err(eyre!("Editor `{}` exited with {}", editor, status));
+                }
+
+                Config::from_dir(&tmp)
+                    .wrap_err_with(|| format!("Your edit is kept in {}", tmp.display()))?;
+                if dry_run {
+                    info!("with --dry-run, furthur actions are avoided.");
+                    return Ok(());
+                }
+                Config::write_raw(path, &fs::read(&tmp)?)?;
+                fs::remove_file(&tmp)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Default for Cmd {
     fn default() -> Self {
         Self::Paste {
             file_name: None,
+            id: None,
             metadata: Default::default(),
+            metadata_file: None,
+            process: Default::default(),
         }
     }
 }
@@ -86,13 +332,24 @@ impl Default for Cmd {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KV {
     k: String,
-    v: String,
+    v: Value,
 }
 
 impl KV {
-    fn as_pair(&self) -> (&str, &str) {
+    fn as_pair(&self) -> (&str, Value) {
         let KV { k, v } = self;
-        (k, v)
+        (k, v.clone())
+    }
+
+    /// Read metadata from a JSON or RON file, chosen by extension
+    fn from_file(path: &Path) -> Result<Vec<Self>> {
+        let content = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Unable to read metadata file {}", path.display()))?;
+        let map: Map<String, Value> = match path.extension().and_then(|x| x.to_str()) {
+            Some("ron") => ron::from_str(&content).wrap_err("Bad RON")?,
+            _ => serde_json::from_str(&content).wrap_err("Bad JSON")?,
+        };
+        Ok(map.into_iter().map(|(k, v)| KV { k, v }).collect())
     }
 }
 
@@ -103,22 +360,96 @@ impl FromStr for KV {
         let mut splitted = s.splitn(2, '=');
         let k = splitted
             .next()
-            .wrap_err("Parse failed. Format: K=V")?
-            .to_string();
+            .wrap_err("Parse failed. Format: K=V or K:=JSON")?;
         let v = splitted
             .next()
-            .wrap_err("Parse failed. Format: K=V")?
-            .to_string();
-        Ok(Self { k, v })
+            .wrap_err("Parse failed. Format: K=V or K:=JSON")?;
+        match k.strip_suffix(':') {
+            Some(k) => Ok(Self {
+                k: k.to_owned(),
+                v: serde_json::from_str(v)
+                    .wrap_err_with(|| format!("Value of `{k}` is not valid JSON"))?,
+            }),
+            None => Ok(Self {
+                k: k.to_owned(),
+                v: v.into(),
+            }),
+        }
+    }
+}
+
+/// Open `path`, or stdin if it's `-`, to be streamed. Stdin is read into
+/// memory unless it's redirected from a file, since the length must be known
+/// beforehand.
+fn open_original(path: &Path) -> Result<(Box<dyn ReadSeek>, u64)> {
+    if path != Path::new("-") {
+        let file =
+            File::open(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+        let len = file.metadata()?.len();
+        return Ok((Box::new(file), len));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+
+        let file = File::from(io::stdin().as_fd().try_clone_to_owned()?);
+        let meta = file.metadata()?;
+        if meta.is_file() {
+            return Ok((Box::new(file), meta.len()));
+        }
     }
+
+    debug!("Stdin is not a file, reading it into memory");
+    let mut bytes = vec![];
+    io::stdin()
+        .read_to_end(&mut bytes)
+        .wrap_err("Failed to read stdin")?;
+    let len = bytes.len() as u64;
+    Ok((Box::new(Cursor::new(bytes)), len))
+}
+
+/// Print summary of `import` or `migrate`, failing if any image failed
+fn report_import(summary: ImportSummary, done: &str, dry_run: bool) -> Result<()> {
+    if dry_run {
+        info!(
+            "Would upload {} images, {} already uploaded",
+            summary.uploaded, summary.skipped
+        );
+        info!("with --dry-run, furthur actions are avoided.");
+        return Ok(());
+    }
+    info!(
+        "{done}: {} uploaded, {} already present, {} failed",
+        summary.uploaded.green(),
+        summary.skipped,
+        summary.failed.len().red()
+    );
+    match summary.failed.into_iter().next() {
+        Some((_, e)) => Err(e).wrap_err("Some images failed to upload, run again to retry"),
+        None => Ok(()),
+    }
+}
+
+/// Load user config and project config, and resolve the profile to use
+fn load_config(flag: &Flag, path: &Path) -> Result<(Config, Profile)> {
+    let mut config = Config::from_dir_or_default(path)?;
+    let project = ProjectConfig::discover()?;
+    let mut profile = config.resolve(flag.profile.as_deref().or(project.profile.as_deref()))?;
+    profile.overlay(&project);
+    config.process = project.process.or(&config.process);
+    config.retry = flag.retry.clone().or(&config.retry);
+    config.client = flag.client.clone().or(&config.client);
+    Ok((config, profile))
 }
 
 impl Opt {
     pub fn handle(self) -> Result<()> {
-        let config_path = home_dir()
-            .wrap_err("Cannot determine home directory")?
-            .join(CONFIG_PATH);
         let flag = self.flag;
+        let config_path = match flag.config {
+            Some(ref path) => path.clone(),
+            None => Config::default_path()?,
+        };
         match self.cmd.unwrap_or_else(|| {
             info!(
                 "Use `{BIN_NAME}` without subcommand defaults to `{BIN_NAME} paste`. If this is \
@@ -127,39 +458,94 @@ impl Opt {
             Default::default()
         }) {
             Cmd::Auth { auth } => {
-                let config = Config::new(auth);
+                let mut config = Config::from_dir_or_default(&config_path)?;
+                let name = config.profile_name(flag.profile.as_deref()).to_owned();
                 info!("Verifying new auth info...");
-                config.as_api()?.verify_token()?;
+                let retry = flag.retry.clone().or(&config.retry);
+                let client = flag.client.clone().or(&config.client);
+                let report = API::new(auth.clone())?
+                    .with_retry(retry)
+                    .with_client(&client)?
+                    .diagnose(!flag.dry_run);
+                report.print(flag.output.unwrap_or_default())?;
+                report
+                    .ensure_passed()
+                    .wrap_err("Unable to verify the auth pair")?;
 
                 if flag.dry_run {
                     info!("with --dry-run, furthur actions are avoided.");
                     return Ok(());
                 }
 
+                config.set_auth(&name, auth);
                 config.write_to(config_path)?;
-                info!("Done adding authentication!");
+                info!("Done adding authentication to profile `{name}`!");
                 Ok(())
             }
+            Cmd::Config { cmd } => cmd.handle(&config_path, flag.profile.as_deref(), flag.dry_run),
+            Cmd::Doctor => {
+                let mut report = Report::default();
+                let mut output = flag.output;
+
+                report.check_config(&config_path);
+                match load_config(&flag, &config_path).and_then(|(config, profile)| {
+                    output = output.or(profile.output);
+                    match profile.backend {
+                        BackendConfig::Cloudflare => Ok(Ok(config.api(&profile)?)),
+                        backend => Ok(Err(backend)),
+                    }
+                }) {
+                    Ok(Ok(api)) => {
+                        report.pass("Auth", format!("account {}", api.account_id()));
+                        report.check_api(&api, !flag.dry_run);
+                    }
+                    Ok(Err(backend)) => report.pass("Backend", backend.to_string()),
+                    Err(e) => {
+                        let hint = e.chain().skip(1).map(|e| e.to_string()).collect::<Vec<_>>();
+                        report.fail("Auth", e.to_string(), Some(hint.join(" ")))
+                    }
+                }
+                report.check_clipboard();
+                report.check_formats();
+
+                report.print(output.unwrap_or_default())?;
+                report.ensure_passed()
+            }
             Cmd::Paste {
                 file_name,
+                id,
                 metadata,
+                metadata_file,
+                process,
             } => {
-                let config = Config::from_dir(config_path)?;
-                let api = config.as_api()?;
-                let mut cb = Clipboard::new()?;
+                let (config, profile) = load_config(&flag, &config_path)?;
+                let backend = config.backend(&profile)?;
+                let output = flag.output.or(profile.output).unwrap_or_default();
+                let variant = flag.variant.as_deref().or(profile.variant.as_deref());
+                let file_meta = metadata_file
+                    .as_deref()
+                    .map(KV::from_file)
+                    .transpose()?
+                    .unwrap_or_default();
+                let mut cb = Clipboard::new().map_err(ShotError::Clipboard)?;
                 let filename = file_name.unwrap_or_else(image_name);
 
-                let image = match cb.get_image() {
-                    Ok(image) => image,
-                    Err(e) => {
-                        error!("Failed to retrieve image data from clipboard");
-                        error!("{}", e);
-                        return Ok(());
-                    }
-                };
+                let image = cb
+                    .get_image()
+                    .map_err(ShotError::Clipboard)
+                    .wrap_err_with(|| {
+                        format!(
+                            "Failed to retrieve image data from clipboard. Run `{BIN_NAME} \
+                             doctor` to check the environment"
+                        )
+                    })?;
 
                 let (w, h) = (image.width, image.height);
-                let png = image_data_to_png(&image)?;
+                let process = process.or(&config.process);
+                let mut image = image_data_to_image(&image)?;
+                process.apply(&mut image)?;
+                let placeholder = process.placeholder(&image)?;
+                let png = process.encode(&image)?;
                 let size = bytesize::to_string(png.len().try_into()?, true);
 
                 info!(
@@ -169,8 +555,33 @@ impl Opt {
                     size.blue()
                 );
 
-                let mut upload = api.upload(&filename, &png);
-                upload.extend_meta(metadata.iter().map(KV::as_pair));
+                let source = Source {
+                    path: None,
+                    width: image.width(),
+                    height: image.height(),
+                    content: &Content::Bytes(&png),
+                };
+                let auto_meta = config
+                    .enrich
+                    .meta(&source)
+                    .into_iter()
+                    .chain(placeholder.iter().flat_map(Placeholder::meta))
+                    .collect::<Vec<_>>();
+
+                let id = profile.custom_id(id.as_deref(), &filename);
+                let mut upload = Upload::new(backend.as_ref(), &filename, Content::Bytes(&png));
+                if let Some(ref id) = id {
+                    upload = upload.id(id);
+                }
+                upload.extend_meta(auto_meta.iter().map(|(k, v)| (*k, v.as_str())));
+                upload.extend_meta(
+                    profile
+                        .metadata
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.clone())),
+                );
+                upload.extend_meta(file_meta.iter().chain(&metadata).map(KV::as_pair));
+                upload.validate()?;
 
                 if flag.dry_run {
                     info!("with --dry-run, furthur actions are avoided.");
@@ -179,7 +590,11 @@ impl Opt {
 
                 info!("Uploading image...");
 
-                upload.send().wrap_err("Failed to upload image")?.log();
+                upload.send().wrap_err("Failed to upload image")?.print(
+                    output,
+                    variant,
+                    placeholder.as_ref(),
+                )?;
 
                 Ok(())
             }
@@ -187,60 +602,116 @@ impl Opt {
             Cmd::Upload {
                 file_path,
                 metadata,
+                metadata_file,
                 file_name,
+                id,
+                format,
+                process,
             } => {
-                let config = Config::from_dir(config_path)?;
-                let api = config.as_api()?;
-
-                info!("Reading file");
-                let img = Reader::open(&file_path)
-                    .wrap_err("Failed to open img file")?
-                    .decode()
-                    .wrap_err("Unsupported img format")?;
-                let mut buf = img
-                    .as_bytes()
-                    .len()
-                    .pipe(Vec::with_capacity)
-                    .pipe(Cursor::new);
-
-                info!("Encoding image");
-
-                img.write_to(&mut buf, image::ImageFormat::Png)
-                    .wrap_err("Unable to encode image")?;
-
-                let (mut w, mut h) = img.dimensions();
-                // let mut buf = buf.get_ref().len();
-                let mut len = buf.get_ref().len();
-
-                // Cloudflare images has a 10 MB size limit
-                // See: https://developers.cloudflare.com/images/cloudflare-images/upload-images/formats-limitations/
-                if len > 10_000_000 {
-                    let size = bytesize::to_string(len.try_into()?, true);
-                    info!("Image too big ({}), resizing", size.yellow());
-                    let ratio = (len as f64 / (3_000_000) as f64).sqrt();
-                    debug!("Resize ratio: {ratio}");
-                    w = (w as f64 / ratio) as u32;
-                    h = (h as f64 / ratio) as u32;
-
-                    let mut vec = buf.into_inner();
-                    vec.clear();
-                    buf = Cursor::new(vec);
-                    img.resize(w, h, FilterType::Gaussian)
-                        .write_to(&mut buf, image::ImageFormat::Png)
-                        .wrap_err("Unable to encode image")?;
-                    len = buf.get_ref().len();
-                }
-                let size = bytesize::to_string(len.try_into()?, true);
-
-                let filename = file_name
-                    .or_else(|| {
-                        file_path
-                            .file_stem()
-                            .and_then(|x| x.to_str().map(ToOwned::to_owned).map(|x| x + ".png"))
-                    })
-                    .unwrap_or_else(image_name);
-
-                let buf = buf.into_inner();
+                let (config, profile) = load_config(&flag, &config_path)?;
+                let backend = config.backend(&profile)?;
+                let output = flag.output.or(profile.output).unwrap_or_default();
+                let variant = flag.variant.as_deref().or(profile.variant.as_deref());
+                let file_meta = metadata_file
+                    .as_deref()
+                    .map(KV::from_file)
+                    .transpose()?
+                    .unwrap_or_default();
+                let process = process.or(&config.process);
+                let stdin = file_path == Path::new("-");
+                let source_path = (!stdin).then_some(file_path.as_path());
+
+                let buf;
+                let (content, content_type, filename, (w, h), placeholder) = match format {
+                    Format::Png => {
+                        info!("Reading file");
+                        let reader = if stdin {
+                            let mut bytes = vec![];
+                            io::stdin()
+                                .read_to_end(&mut bytes)
+                                .wrap_err("Failed to read stdin")?;
+                            Reader::new(Cursor::new(bytes))
+                                .with_guessed_format()?
+                                .decode()
+                        } else {
+                            Reader::open(&file_path)
+                                .wrap_err("Failed to open img file")?
+                                .decode()
+                        };
+                        let mut img = reader
+                            .map_err(ShotError::Decode)
+                            .wrap_err_with(|| format!("Failed to read {}", file_path.display()))?;
+                        process.apply(&mut img)?;
+                        let placeholder = process.placeholder(&img)?;
+
+                        info!("Encoding image");
+
+                        let mut png = process.encode(&img)?;
+                        let (mut w, mut h) = img.dimensions();
+
+                        // E.g. Cloudflare images has a 10 MB size limit
+                        if backend.size_limit().is_some_and(|limit| png.len() > limit) {
+                            let size = bytesize::to_string(png.len().try_into()?, true);
+                            info!("Image too big ({}), resizing", size.yellow());
+                            let ratio = (png.len() as f64 / (3_000_000) as f64).sqrt();
+                            debug!("Resize ratio: {ratio}");
+                            w = (w as f64 / ratio) as u32;
+                            h = (h as f64 / ratio) as u32;
+
+                            png = process.encode(&img.resize(w, h, FilterType::Gaussian))?;
+                        }
+
+                        let filename = file_name
+                            .or_else(|| {
+                                source_path.and_then(Path::file_stem).and_then(|x| {
+                                    x.to_str().map(ToOwned::to_owned).map(|x| x + ".png")
+                                })
+                            })
+                            .unwrap_or_else(image_name);
+                        buf = png;
+                        (
+                            Content::Bytes(&buf),
+                            "image/png",
+                            filename,
+                            (w, h),
+                            placeholder,
+                        )
+                    }
+                    Format::Original => {
+                        if process.modifies() {
+                            warn!(
+                                "`--format original` uploads the file as is, image processing \
+                                 options are ignored"
+                            );
+                        }
+                        let (mut reader, len) = open_original(&file_path)?;
+                        let image = Reader::new(BufReader::new(&mut reader))
+                            .with_guessed_format()
+                            .wrap_err("Failed to read image header")?;
+                        let format = image.format().wrap_err("Unknown image format")?;
+                        let content_type = mime_type(format).wrap_err_with(|| {
+                            format!("{format:?} images are not supported by Cloudflare Images")
+                        })?;
+                        let dimensions = image
+                            .into_dimensions()
+                            .map_err(ShotError::Decode)
+                            .wrap_err_with(|| format!("Failed to read {}", file_path.display()))?;
+
+                        let filename = file_name
+                            .or_else(|| {
+                                source_path
+                                    .and_then(Path::file_name)
+                                    .and_then(|x| x.to_str().map(ToOwned::to_owned))
+                            })
+                            .unwrap_or_else(|| {
+                                let ext = format.extensions_str().first().unwrap_or(&"img");
+                                image_name().replace(".png", &format!(".{ext}"))
+                            });
+                        let content = Content::reader(reader, len);
+                        (content, content_type, filename, dimensions, None)
+                    }
+                };
+                let size = bytesize::to_string(content.len(), true);
 
                 info!(
                     "Image ({}): {} x {}, {}",
@@ -250,8 +721,41 @@ impl Opt {
                     size.blue()
                 );
 
-                let mut upload = api.upload(&filename, &buf);
-                upload.extend_meta(metadata.iter().map(|KV { k, v }| (k.as_str(), v.as_str())));
+                let source = Source {
+                    path: source_path,
+                    width: w,
+                    height: h,
+                    content: &content,
+                };
+                let auto_meta = config
+                    .enrich
+                    .meta(&source)
+                    .into_iter()
+                    .chain(placeholder.iter().flat_map(Placeholder::meta))
+                    .collect::<Vec<_>>();
+
+                let bar = ProgressBar::new();
+                let progress = |sent, total| {
+                    if let Some(ref bar) = bar {
+                        bar.update(sent, total)
+                    }
+                };
+                let id = profile.custom_id(id.as_deref(), &filename);
+                let mut upload = Upload::new(backend.as_ref(), &filename, content)
+                    .content_type(content_type)
+                    .progress(&progress);
+                if let Some(ref id) = id {
+                    upload = upload.id(id);
+                }
+                upload.extend_meta(auto_meta.iter().map(|(k, v)| (*k, v.as_str())));
+                upload.extend_meta(
+                    profile
+                        .metadata
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.clone())),
+                );
+                upload.extend_meta(file_meta.iter().chain(&metadata).map(KV::as_pair));
+                upload.validate()?;
 
                 if flag.dry_run {
                     info!("with --dry-run, furthur actions are avoided.");
@@ -260,10 +764,113 @@ impl Opt {
 
                 info!("Uploading image...");
 
-                upload.send().wrap_err("Failed to upload image")?.log();
+                let image = upload.send();
+                // Clear the bar before printing anything else
+                drop(bar);
+                image.wrap_err("Failed to upload image")?.print(
+                    output,
+                    variant,
+                    placeholder.as_ref(),
+                )?;
 
                 Ok(())
             }
+            Cmd::Export {
+                dir,
+                manifest,
+                jobs,
+            } => {
+                let (config, profile) = load_config(&flag, &config_path)?;
+                let api = config.api(&profile)?;
+                let summary = Export::new(&api, &dir)
+                    .variant(flag.variant.clone())
+                    .format(manifest)
+                    .jobs(jobs)
+                    .dry_run(flag.dry_run)
+                    .run()?;
+
+                if flag.dry_run {
+                    info!(
+                        "Would download {} images, {} already exported",
+                        summary.downloaded, summary.skipped
+                    );
+                    info!("with --dry-run, furthur actions are avoided.");
+                    return Ok(());
+                }
+                info!(
+                    "Exported into {}: {} downloaded, {} already present, {} failed",
+                    dir.display(),
+                    summary.downloaded.green(),
+                    summary.skipped,
+                    summary.failed.len().red()
+                );
+                match summary.failed.into_iter().next() {
+                    Some((_, e)) => {
+                        Err(e).wrap_err("Some images failed to export, run again to retry")
+                    }
+                    None => Ok(()),
+                }
+            }
+            Cmd::Import { path } => {
+                let (config, profile) = load_config(&flag, &config_path)?;
+                let backend = config.backend(&profile)?;
+                let (manifest, dir) = Manifest::open(&path)?;
+                let summary =
+                    Import::new(backend.as_ref(), ImportSource::Manifest { manifest, dir })
+                        .dry_run(flag.dry_run)
+                        .run()?;
+                report_import(summary, "Imported", flag.dry_run)
+            }
+            Cmd::Migrate { from, to } => {
+                if from == to {
+                    return Err(eyre!("`--from` and `--to` are the same profile"));
+                }
+                let mut config = Config::from_dir_or_default(&config_path)?;
+                config.retry = flag.retry.clone().or(&config.retry);
+                config.client = flag.client.clone().or(&config.client);
+                let source = config.resolve_other(&from, flag.profile.as_deref())?;
+                if !source.backend.is_cloudflare() {
+                    return Err(ShotError::Config(format!(
+                        "Profile `{from}` is not of Cloudflare Images, which images can only be \
+                         migrated from"
+                    ))
+                    .into());
+                }
+                let target = config.resolve_other(&to, flag.profile.as_deref())?;
+                if target.backend.is_cloudflare()
+                    && target.auth.account_id == source.auth.account_id
+                {
+                    return Err(eyre!("`{from}` and `{to}` are the same account"));
+                }
+                let api = config.api(&source)?;
+                let backend = config.backend(&target)?;
+                let summary = Import::new(backend.as_ref(), ImportSource::Account(&api))
+                    .dry_run(flag.dry_run)
+                    .run()?;
+                report_import(summary, "Migrated", flag.dry_run)
+            }
+            Cmd::MockServer {
+                addr,
+                dir,
+                token,
+                faults,
+            } => {
+                let mut mock = MockServer::bind(&addr)?.with_token(token);
+                if let Some(dir) = dir {
+                    mock = mock.with_dir(dir)?;
+                }
+                faults.into_iter().for_each(|fault| mock.fail(fault));
+                let auth = mock.auth();
+                info!("Serving mock API at {}", mock.url());
+                info!(
+                    "Use it with `--api-base {}`, account ID `{}` and token `{}`",
+                    mock.url(),
+                    auth.account_id,
+                    auth.token
+                );
+                mock.serve();
+                Ok(())
+            }
         }
     }
 }
@@ -0,0 +1,176 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use attohttpc::{ProxySettings, Session};
+use clap::Args;
+use color_eyre::{eyre::eyre, Result};
+use native_tls::Certificate;
+use serde::{Deserialize as De, Serialize as Ser};
+use url::Url;
+
+use crate::{ShotError, API};
+
+/// HTTP settings of API requests.
+///
+/// Also used in [`Config`](crate::Config) as defaults, where flags given on
+/// the command line take precedence.
+#[derive(Args, Ser, De, Debug, Clone, Default)]
+#[clap(about = None, long_about = None)]
+#[group(skip)]
+#[serde(default)]
+pub struct Client {
+    #[clap(long, global = true, env = "SHOT_API_BASE", value_name = "URL")]
+    /// Base URL of Cloudflare API, e.g. a mock server or an egress proxy
+    /// [default: https://api.cloudflare.com/client/v4/]
+    pub api_base: Option<Url>,
+
+    #[clap(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    /// Timeout of establishing a connection [default: 30s]
+    #[serde(with = "humantime_serde")]
+    pub connect_timeout: Option<Duration>,
+
+    #[clap(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    /// Timeout of each read from a connection [default: 30s]
+    #[serde(with = "humantime_serde")]
+    pub read_timeout: Option<Duration>,
+
+    #[clap(long, global = true, value_name = "URL")]
+    /// Proxy for all requests [default: `HTTPS_PROXY`, `HTTP_PROXY` or
+    /// `ALL_PROXY`, respecting `NO_PROXY`]
+    pub proxy: Option<Url>,
+
+    #[clap(long = "ca-cert", global = true, value_name = "FILE")]
+    /// Extra root certificate to trust, in PEM or DER. Takes multiple values
+    pub ca_certs: Vec<PathBuf>,
+}
+
+impl Client {
+    /// Fill options not given in `self` with those in `defaults`
+    pub fn or(self, defaults: &Client) -> Client {
+        let defaults = defaults.clone();
+        Client {
+            api_base: self.api_base.or(defaults.api_base),
+            connect_timeout: self.connect_timeout.or(defaults.connect_timeout),
+            read_timeout: self.read_timeout.or(defaults.read_timeout),
+            proxy: self.proxy.or(defaults.proxy),
+            ca_certs: if self.ca_certs.is_empty() {
+                defaults.ca_certs
+            } else {
+                self.ca_certs
+            },
+        }
+    }
+
+    /// Base URL, with a trailing slash so paths can be joined onto it
+    pub fn api_base(&self) -> Url {
+        let mut base = self
+            .api_base
+            .clone()
+            .unwrap_or_else(|| Url::parse(API::API).unwrap());
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        base
+    }
+
+    pub fn session(&self) -> Result<Session, ShotError> {
+        let mut session = Session::new();
+        if let Some(timeout) = self.connect_timeout {
+            session.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            session.read_timeout(timeout);
+        }
+        // Without explicit proxy, env vars are used by default
+        if let Some(ref proxy) = self.proxy {
+            session.proxy_settings(
+                ProxySettings::builder()
+                    .http_proxy(proxy.clone())
+                    .https_proxy(proxy.clone())
+                    .build(),
+            );
+        }
+        for (path, cert) in self.certs()? {
+            let cert = match cert {
+                Cert::Pem(pem) => Certificate::from_pem(&pem),
+                Cert::Der(der) => Certificate::from_der(&der),
+            }
+            .map_err(|e| bad_cert(path, e))?;
+            session.add_root_certificate(cert);
+        }
+        Ok(session)
+    }
+
+    /// Async HTTP client with the same settings as [`Client::session`].
+    /// `read_timeout` limits whole requests, as reqwest has no per-read
+    /// timeout.
+    #[cfg(feature = "async")]
+    pub fn async_client(&self) -> Result<reqwest::Client, ShotError> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(ref proxy) = self.proxy {
+            let proxy = reqwest::Proxy::all(proxy.as_str())
+                .map_err(|e| ShotError::Config(format!("Bad proxy `{proxy}`: {e}")))?;
+            builder = builder.proxy(proxy);
+        }
+        for (path, cert) in self.certs()? {
+            let cert = match cert {
+                Cert::Pem(pem) => reqwest::Certificate::from_pem(&pem),
+                Cert::Der(der) => reqwest::Certificate::from_der(&der),
+            }
+            .map_err(|e| bad_cert(path, e))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        builder
+            .build()
+            .map_err(|e| ShotError::Config(format!("Unable to build HTTP client: {e}")))
+    }
+
+    /// Certificates of all `ca_certs`
+    fn certs(&self) -> Result<Vec<(&Path, Cert)>, ShotError> {
+        let mut certs = vec![];
+        for path in &self.ca_certs {
+            let content = read_certs(path).map_err(|e| bad_cert(path, e))?;
+            certs.extend(content.into_iter().map(|cert| (path.as_path(), cert)));
+        }
+        Ok(certs)
+    }
+}
+
+fn bad_cert(path: &Path, e: impl Display) -> ShotError {
+    ShotError::Config(format!("Bad certificate {}: {e}", path.display()))
+}
+
+/// Certificate content, parsed by whichever TLS backend is in use
+enum Cert {
+    Pem(Vec<u8>),
+    Der(Vec<u8>),
+}
+
+/// All certificates in a PEM bundle, or the single one in a DER file
+fn read_certs(path: &Path) -> Result<Vec<Cert>> {
+    let content = fs::read(path)?;
+    let Ok(pem) = std::str::from_utf8(&content) else {
+        return Ok(vec![Cert::Der(content)]);
+    };
+
+    const END: &str = "-----END CERTIFICATE-----";
+    let certs = pem
+        .split_inclusive(END)
+        .filter(|block| block.contains(END))
+        .map(|block| Cert::Pem(block.as_bytes().to_vec()))
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(eyre!("No certificate found"));
+    }
+    Ok(certs)
+}
@@ -1,65 +1,657 @@
 use std::{
-    fs::{self, File},
-    path::Path,
+    collections::BTreeMap,
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
 };
 
 use clap::Parser;
 use color_eyre::{
-    eyre::{eyre, Context},
+    eyre::{eyre, Context, ContextCompat},
     Result,
 };
+use home::home_dir;
+use log::{debug, info, warn};
 use serde::{Deserialize as De, Serialize as Ser};
+use serde_json::{Map, Value};
 
-use crate::{API, BIN_NAME, CONFIG_PATH};
+use crate::{
+    Backend, BackendConfig, Client, Custom, Enrich, Local, Output, Process, Retry, ShotError, API,
+    BIN_NAME, S3,
+};
+
+/// Environment variable of account id, takes precedence over config
+pub const ACCOUNT_ID_ENV: &str = "SHOT_ACCOUNT_ID";
 
-#[derive(Ser, De, Debug)]
+/// Environment variable of API token, takes precedence over config
+pub const TOKEN_ENV: &str = "SHOT_API_TOKEN";
+
+#[derive(Ser, De, Debug, Default)]
 pub struct Config {
-    pub auth: Auth,
+    /// Profile used when neither `--profile` nor `SHOT_PROFILE` is set.
+    /// Falls back to [`Config::DEFAULT_PROFILE`].
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Default image processing options, overridden by flags
+    #[serde(default)]
+    pub process: Process,
+    /// Metadata automatically added to every upload
+    #[serde(default)]
+    pub enrich: Enrich,
+    /// Retry and rate limit of API requests, overridden by flags
+    #[serde(default)]
+    pub retry: Retry,
+    /// HTTP settings of API requests, overridden by flags
+    #[serde(default)]
+    pub client: Client,
+    /// Where this config is loaded from
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// Config before profiles were introduced, with a single `auth`
+#[derive(De)]
+struct LegacyConfig {
+    auth: Auth,
+    #[serde(default)]
+    process: Process,
+    #[serde(default)]
+    enrich: Enrich,
+}
+
+impl From<LegacyConfig> for Config {
+    fn from(legacy: LegacyConfig) -> Self {
+        let LegacyConfig {
+            auth,
+            process,
+            enrich,
+        } = legacy;
+        Self {
+            default_profile: None,
+            profiles: [(Self::DEFAULT_PROFILE.to_owned(), Profile::new(auth))].into(),
+            process,
+            enrich,
+            retry: Default::default(),
+            client: Default::default(),
+            path: PathBuf::new(),
+        }
+    }
 }
 
 impl Config {
+    pub const DEFAULT_PROFILE: &'static str = "default";
+
+    /// `$XDG_CONFIG_HOME/shot.ron` if `XDG_CONFIG_HOME` is set to an absolute
+    /// path, otherwise `~/.config/shot.ron`
+    pub fn default_path() -> Result<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME").map(PathBuf::from) {
+            Some(dir) if dir.is_absolute() => dir,
+            _ => home_dir()
+                .wrap_err("Cannot determine home directory")?
+                .join(".config"),
+        };
+        Ok(dir.join(format!("{BIN_NAME}.ron")))
+    }
+
     pub fn from_dir(path: impl AsRef<Path>) -> Result<Self> {
         let path = path.as_ref();
         if path.exists() {
+            warn_if_readable_by_others(path);
             let content = String::from_utf8(fs::read(path)?)?;
-            Ok(ron::from_str(&content)?)
+            // Only legacy config has top-level `auth`. It's not rewritten
+            // here, as reading never changes files, but saved as profiles by
+            // the next command that writes config.
+            if let Ok(legacy) = ron::from_str::<LegacyConfig>(&content) {
+                let mut config = Config::from(legacy);
+                config.path = path.to_owned();
+                info!(
+                    "Reading legacy config, its auth is used as profile `{}` and saved as one on \
+                     the next change",
+                    Self::DEFAULT_PROFILE
+                );
+                return Ok(config);
+            }
+            let mut config: Config = ron::from_str(&content).map_err(|e| {
+                ShotError::Config(format!("Bad config file {}: {e}", path.display()))
+            })?;
+            config.path = path.to_owned();
+            Ok(config)
+        } else {
+            no_auth_error(path)
+        }
+    }
+
+    /// Same as [`Config::from_dir`], but an empty config is returned if the
+    /// file does not exist
+    pub fn from_dir_or_default(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            Self::from_dir(path)
         } else {
-            let err = eyre!("For more help, see https://github.com/George-Miao/shot#help")
-                .wrap_err(format!(
-                    "Use `{BIN_NAME} auth <account_id> <token>` or manually edit \
-                     `~/{CONFIG_PATH}`."
-                ))
-                .wrap_err("You haven't config your authentication info yet");
-            Err(err)
+            Ok(Self {
+                path: path.to_owned(),
+                ..Default::default()
+            })
         }
     }
 
+    /// API client of `profile`, with retry and HTTP settings in this config
+    pub fn api(&self, profile: &Profile) -> Result<API, ShotError> {
+        profile
+            .as_api()?
+            .with_retry(self.retry.clone())
+            .with_client(&self.client)
+    }
+
+    /// Storage backend of `profile`, with retry and HTTP settings in this
+    /// config
+    pub fn backend(&self, profile: &Profile) -> Result<Box<dyn Backend>, ShotError> {
+        Ok(match profile.backend {
+            BackendConfig::Cloudflare => Box::new(self.api(profile)?),
+            BackendConfig::Local {
+                ref dir,
+                ref base_url,
+            } => Box::new(Local::new(dir, base_url.clone())),
+            BackendConfig::S3(ref s3) => {
+                Box::new(S3::new(s3.clone(), &self.client, self.retry.clone())?)
+            }
+            BackendConfig::Custom(ref custom) => Box::new(Custom::new(
+                custom.clone(),
+                &self.client,
+                self.retry.clone(),
+            )?),
+        })
+    }
+
+    /// Resolve the name of the profile to use. `name` comes from `--profile`
+    /// or `SHOT_PROFILE`.
+    pub fn profile_name<'a>(&'a self, name: Option<&'a str>) -> &'a str {
+        name.or(self.default_profile.as_deref())
+            .unwrap_or(Self::DEFAULT_PROFILE)
+    }
+
+    /// Profile `name`, the one in use, with its auth resolved. Environment
+    /// variables [`ACCOUNT_ID_ENV`] and [`TOKEN_ENV`] take precedence over
+    /// config, and `token_command` is run if no token is given otherwise. If
+    /// both environment variables are set, no profile is needed in config.
+    /// Profiles of other backends need no token.
+    pub fn resolve(&self, name: Option<&str>) -> Result<Profile> {
+        self.resolve_auth(name, true)
+    }
+
+    /// Profile `name` with its auth resolved, for commands using several
+    /// profiles. Environment variables only apply if it's the profile in use,
+    /// `active` or the default one, so they can't make every profile the same
+    /// account.
+    pub fn resolve_other(&self, name: &str, active: Option<&str>) -> Result<Profile> {
+        self.resolve_auth(Some(name), name == self.profile_name(active))
+    }
+
+    fn resolve_auth(&self, name: Option<&str>, from_env: bool) -> Result<Profile> {
+        let var = |name| {
+            env::var(name)
+                .ok()
+                .filter(|x: &String| from_env && !x.is_empty())
+        };
+        let account_id = var(ACCOUNT_ID_ENV);
+        let token = var(TOKEN_ENV);
+
+        let mut profile = match (self.profile(name), account_id.clone(), token.clone()) {
+            (Ok(profile), ..) => profile.clone(),
+            (Err(_), Some(account_id), Some(token)) => Profile::new(Auth { account_id, token }),
+            (Err(_), ..) if self.profiles.is_empty() => return no_auth_error(&self.path),
+            (Err(e), ..) => return Err(e),
+        };
+
+        if let Some(account_id) = account_id {
+            debug!("Using account id from `{ACCOUNT_ID_ENV}`");
+            profile.auth.account_id = account_id;
+        }
+        if let Some(token) = token {
+            debug!("Using token from `{TOKEN_ENV}`");
+            profile.auth.token = token;
+        }
+        if !profile.backend.is_cloudflare() {
+            return Ok(profile);
+        }
+        if profile.auth.account_id.is_empty() {
+            let name = self.profile_name(name);
+            return Err(color_eyre::Report::new(ShotError::Auth(format!(
+                "Set `auth` in the profile, or set `{ACCOUNT_ID_ENV}`"
+            )))
+            .wrap_err(format!("No account id for profile `{name}`")));
+        }
+        if profile.auth.token.is_empty() {
+            let name = self.profile_name(name);
+            let cmd = profile.token_command.as_deref().ok_or_else(|| {
+                color_eyre::Report::new(ShotError::Auth(format!(
+                    "Set `token` or `token_command` in the profile, or set `{TOKEN_ENV}`"
+                )))
+                .wrap_err(format!("No token for profile `{name}`"))
+            })?;
+            profile.auth.token = run_token_command(cmd)?;
+        }
+
+        Ok(profile)
+    }
+
+    pub fn profile(&self, name: Option<&str>) -> Result<&Profile> {
+        let name = self.profile_name(name);
+        self.profiles
+            .get(name)
+            .ok_or_else(|| {
+                let available = self
+                    .profiles
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                ShotError::Config(format!("Available profiles: [{available}]"))
+            })
+            .wrap_err_with(|| {
+                format!("Use `{BIN_NAME} auth --profile {name} <account_id> <token>` to create it")
+            })
+            .wrap_err_with(|| format!("Profile `{name}` not found"))
+    }
+
+    /// Set auth of profile `name`, creating the profile if it does not exist.
+    /// The first profile created becomes the default one.
+    pub fn set_auth(&mut self, name: &str, auth: Auth) {
+        if self.profiles.is_empty() && name != Self::DEFAULT_PROFILE {
+            self.default_profile = Some(name.to_owned());
+        }
+        match self.profiles.get_mut(name) {
+            Some(profile) => profile.auth = auth,
+            None => {
+                self.profiles.insert(name.to_owned(), Profile::new(auth));
+            }
+        }
+    }
+
+    /// Set backend of profile `name`, creating the profile if it does not
+    /// exist. The first profile created becomes the default one.
+    pub fn set_backend(&mut self, name: &str, backend: BackendConfig) {
+        if self.profiles.is_empty() && name != Self::DEFAULT_PROFILE {
+            self.default_profile = Some(name.to_owned());
+        }
+        self.profiles
+            .entry(name.to_owned())
+            .or_insert_with(|| Profile::new(Default::default()))
+            .backend = backend;
+    }
+
+    /// Write config to `path`, see [`Config::write_raw`]
+    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = ron::ser::to_string_pretty(self, Default::default())
+            .wrap_err("Unable to serialize config")?;
+        Self::write_raw(path.as_ref(), content.as_bytes())
+    }
+
+    /// Write `content`, e.g. a config edited by hand, to `path`. Missing
+    /// parent directories are created, and the file is written with `0600`
+    /// permissions then renamed into place, so a crash never leaves a
+    /// half-written config and tokens are never readable by other users.
+    pub fn write_raw(path: &Path, content: &[u8]) -> Result<()> {
+        write_private(path, content)
+    }
+
+    fn to_value(&self) -> Result<Value> {
+        serde_json::to_value(self).wrap_err("Unable to serialize config")
+    }
+
+    /// Replace self with `value`, validated against the config schema
+    fn replace_with(&mut self, value: Value) -> Result<()> {
+        let config: Config = serde_json::from_value(value).wrap_err("Invalid config")?;
+        *self = Config {
+            path: std::mem::take(&mut self.path),
+            ..config
+        };
+        Ok(())
+    }
+
+    /// Value of dotted `key`, e.g. `profiles.default.variant`
+    pub fn get(&self, key: &str) -> Result<Value> {
+        let root = self.to_value()?;
+        lookup(&root, key).cloned()
+    }
+
+    /// Set dotted `key` to `value`. Missing entries of maps like `profiles`
+    /// are created along the way.
+    pub fn set(&mut self, key: &str, value: Value) -> Result<()> {
+        let mut root = self.to_value()?;
+        let mut cur = &mut root;
+        for seg in key.split('.') {
+            let Value::Object(map) = cur else {
+                return Err(eyre!("`{}` is not a table", key));
+            };
+            cur = map.entry(seg).or_insert(Value::Object(Map::new()));
+        }
+        *cur = value;
+
+        self.replace_with(root)
+            .wrap_err_with(|| format!("Invalid value for `{key}`"))?;
+
+        // Unknown fields are silently dropped by serde, catch them here
+        lookup(&self.to_value()?, key).map(drop)
+    }
+
+    /// Remove dotted `key`, so it falls back to default, or remove an entry
+    /// from a map
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        let mut root = self.to_value()?;
+        let (parent, last) = key.rsplit_once('.').unwrap_or(("", key));
+        let removed = match parent {
+            "" => root.as_object_mut(),
+            parent => lookup_mut(&mut root, parent)?.as_object_mut(),
+        }
+        .and_then(|map| map.remove(last));
+        if removed.is_none() {
+            lookup(&root, key)?;
+        }
+
+        self.replace_with(root)
+            .wrap_err_with(|| format!("`{key}` is required and cannot be unset"))
+    }
+
+    /// All leaf keys and their values, with tokens and secrets redacted
+    pub fn list(&self) -> Result<Vec<(String, Value)>> {
+        fn walk(prefix: &str, value: &Value, out: &mut Vec<(String, Value)>) {
+            match value {
+                Value::Object(map) if !map.is_empty() => map.iter().for_each(|(k, v)| {
+                    let key = match prefix {
+                        "" => k.to_owned(),
+                        prefix => format!("{prefix}.{k}"),
+                    };
+                    walk(&key, v, out)
+                }),
+                Value::String(secret) if sensitive(prefix) && !secret.is_empty() => {
+                    out.push((prefix.to_owned(), "<redacted>".into()))
+                }
+                value => out.push((prefix.to_owned(), value.clone())),
+            }
+        }
+
+        let mut out = vec![];
+        walk("", &self.to_value()?, &mut out);
+        Ok(out)
+    }
+}
+
+/// Whether value of `key` may hold a credential. Headers, query parameters
+/// and form fields of custom uploaders are all redacted, as API keys can be
+/// under any name there.
+fn sensitive(key: &str) -> bool {
+    const NAMES: &[&str] = &["token", "secret", "password", "authorization", "api_key"];
+    let mut segments = key.rsplit('.');
+    let last = segments.next().unwrap_or_default().to_lowercase();
+    let parent = segments.next();
+    let custom = segments.next() == Some("Custom");
+    (custom && matches!(parent, Some("headers" | "parameters" | "fields")))
+        || NAMES.iter().any(|x| last.replace('-', "_").contains(x))
+}
+
+fn unknown_key(key: &str, parent: &Value) -> color_eyre::Report {
+    let available = match parent {
+        Value::Object(map) => map
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => String::new(),
+    };
+    eyre!("Available keys here: [{}]", available).wrap_err(format!("Unknown key `{key}`"))
+}
+
+fn lookup<'a>(root: &'a Value, key: &str) -> Result<&'a Value> {
+    key.split('.').try_fold(root, |cur, seg| {
+        cur.get(seg).ok_or_else(|| unknown_key(key, cur))
+    })
+}
+
+fn lookup_mut<'a>(root: &'a mut Value, key: &str) -> Result<&'a mut Value> {
+    lookup(root, key)?;
+    Ok(key.split('.').fold(root, |cur, seg| {
+        cur.get_mut(seg).expect("Checked by lookup")
+    }))
+}
+
+/// Write `content` to `path`. Missing parent directories are created, and
+/// the file is written to a temporary file with `0600` permissions first
+/// then renamed, so a crash never leaves a half-written config and the
+/// token is never readable by other users.
+pub(crate) fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Unable to create directory {}", dir.display()))?;
+    }
+
+    let tmp = path.with_extension(format!("ron.{}.tmp", std::process::id()));
+    let write = || -> Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&tmp)
+            .wrap_err_with(|| format!("Bad path {}", tmp.display()))?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    };
+
+    write().map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        e.wrap_err(format!("Unable to write config file to {}", path.display()))
+    })
+}
+
+fn warn_if_readable_by_others(path: &Path) {
+    if readable_by_others(path) {
+        warn!(
+            "Config file {} is accessible by other users and may leak your token. Run `chmod \
+             600 {}` to fix it.",
+            path.display(),
+            path.display()
+        )
+    }
+}
+
+/// Whether group or other users have any permission on `path`
+#[cfg(unix)]
+pub(crate) fn readable_by_others(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).is_ok_and(|meta| meta.permissions().mode() & 0o077 != 0)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn readable_by_others(_: &Path) -> bool {
+    false
+}
+
+fn no_auth_error<T>(path: &Path) -> Result<T> {
+    Err(ShotError::Auth(
+        "For more help, see https://github.com/George-Miao/shot#help".to_owned(),
+    ))
+    .wrap_err_with(|| {
+        format!(
+            "Use `{BIN_NAME} auth <account_id> <token>`, manually edit `{}` (a \
+             `token_command` can be used instead of a plaintext token), or set \
+             `{ACCOUNT_ID_ENV}` and `{TOKEN_ENV}`.",
+            path.display()
+        )
+    })
+    .wrap_err("You haven't config your authentication info yet")
+}
+
+/// Run `cmd` with system shell and read the token from its stdout
+fn run_token_command(cmd: &str) -> Result<String> {
+    debug!("Running token command `{cmd}`");
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", cmd]);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.args(["-c", cmd]);
+        command
+    };
+    // Helpers like `pass` may prompt for passphrase
+    let output = command
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| ShotError::Auth(format!("Failed to run token command `{cmd}`: {e}")))?;
+    if !output.status.success() {
+        let status = output.status;
+        return Err(ShotError::Auth(format!("Token command `{cmd}` exited with {status}")).into());
+    }
+    let token = String::from_utf8(output.stdout)
+        .map_err(|_| ShotError::Auth("Token command printed non UTF-8 output".to_owned()))?
+        .trim()
+        .to_owned();
+    if token.is_empty() {
+        return Err(ShotError::Auth(format!("Token command `{cmd}` printed nothing")).into());
+    }
+    Ok(token)
+}
+
+/// A named set of credentials and preferences
+#[derive(Ser, De, Debug, Clone)]
+pub struct Profile {
+    /// Cloudflare credentials, not needed by other backends
+    #[serde(default)]
+    pub auth: Auth,
+    /// Where images are uploaded to [default: Cloudflare]
+    #[serde(default)]
+    pub backend: BackendConfig,
+    /// Variant shown in output, e.g. `public`. All variants are shown if not
+    /// set.
+    #[serde(default)]
+    pub variant: Option<String>,
+    /// Metadata added to every upload, overridden by `--metadata`
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    /// Default of `--output`
+    #[serde(default)]
+    pub output: Option<Output>,
+    /// Shell command that prints the token to stdout, used when `auth` has
+    /// no token. E.g. `pass show cf/images`
+    #[serde(default)]
+    pub token_command: Option<String>,
+    /// Prefix of custom image IDs. When set, images are uploaded with ID
+    /// `{id_prefix}{id}`, where `id` defaults to the file name.
+    #[serde(default)]
+    pub id_prefix: Option<String>,
+}
+
+impl Profile {
     pub fn new(auth: Auth) -> Self {
-        Self { auth }
+        Self {
+            auth,
+            backend: Default::default(),
+            variant: None,
+            metadata: Default::default(),
+            output: None,
+            token_command: None,
+            id_prefix: None,
+        }
+    }
+
+    /// Apply settings from project config over this profile
+    pub fn overlay(&mut self, project: &ProjectConfig) {
+        self.variant = project.variant.clone().or(self.variant.take());
+        self.output = project.output.or(self.output);
+        self.id_prefix = project.id_prefix.clone().or(self.id_prefix.take());
+        self.metadata.extend(project.metadata.clone());
     }
 
-    pub fn into_api(self) -> Result<API> {
+    /// Custom ID of an upload, `None` to let Cloudflare generate one
+    pub fn custom_id(&self, id: Option<&str>, filename: &str) -> Option<String> {
+        match (&self.id_prefix, id) {
+            (Some(prefix), id) => Some(format!("{prefix}{}", id.unwrap_or(filename))),
+            (None, id) => id.map(ToOwned::to_owned),
+        }
+    }
+
+    pub fn into_api(self) -> Result<API, ShotError> {
         API::new(self.auth)
     }
 
-    pub fn as_api(&self) -> Result<API> {
+    pub fn as_api(&self) -> Result<API, ShotError> {
         API::new(self.auth.clone())
     }
+}
 
-    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<()> {
-        let path = path.as_ref();
-        let mut file =
-            File::create(path).wrap_err_with(|| format!("Bad path {}", path.display()))?;
-        ron::ser::to_writer_pretty(&mut file, self, Default::default())
-            .wrap_err_with(|| format!("Unable to write config file to {}", path.display()))?;
-        Ok(())
+/// Per-repository settings in `.shot.ron`, found in the current directory or
+/// its parents and merged over user config. Credentials are not allowed here.
+#[derive(Ser, De, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Profile to use, unless `--profile` or `SHOT_PROFILE` is set
+    pub profile: Option<String>,
+    pub variant: Option<String>,
+    pub output: Option<Output>,
+    pub id_prefix: Option<String>,
+    /// Merged over metadata of the profile
+    pub metadata: Map<String, Value>,
+    /// Merged over `process` of user config
+    pub process: Process,
+}
+
+impl ProjectConfig {
+    pub const FILE_NAME: &'static str = ".shot.ron";
+
+    /// Find the nearest project config from current directory upwards. An
+    /// empty one is returned if there's none.
+    pub fn discover() -> Result<Self> {
+        match Self::discover_path()? {
+            Some(path) => Self::from_path(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Path of the nearest project config, if any
+    pub fn discover_path() -> Result<Option<PathBuf>> {
+        let cwd = env::current_dir().wrap_err("Cannot determine current directory")?;
+        Ok(cwd
+            .ancestors()
+            .map(|dir| dir.join(Self::FILE_NAME))
+            .find(|path| path.is_file()))
+    }
+
+    /// Read project config at `path`. Relative paths in `process` are
+    /// relative to its directory, not the current one.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        debug!("Using project config {}", path.display());
+        let content = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Unable to read project config {}", path.display()))?;
+        let mut config: Self = ron::from_str(&content)
+            .wrap_err_with(|| format!("Bad project config {}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            let process = &mut config.process;
+            for file in [&mut process.watermark, &mut process.redact_preset]
+                .into_iter()
+                .flatten()
+            {
+                *file = dir.join(&*file);
+            }
+        }
+        Ok(config)
     }
 }
 
-#[derive(Ser, De, Debug, Parser, Clone, PartialEq, Eq)]
+#[derive(Ser, De, Debug, Parser, Clone, Default, PartialEq, Eq)]
 #[group(skip)]
 pub struct Auth {
     pub account_id: String,
+    /// Can be left empty in config in favor of `token_command`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub token: String,
 }
 
@@ -0,0 +1,836 @@
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap},
+    time::SystemTime,
+};
+
+use attohttpc::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
+    RequestBuilder,
+};
+use log::{debug, warn};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use serde::{Deserialize as De, Serialize as Ser};
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    unsupported, Backend, Client, Draft, Field, Form, Image, RateLimiter, Raw, Retry, ShotError,
+};
+
+type Result<T, E = ShotError> = std::result::Result<T, E>;
+
+/// Method of upload requests
+#[derive(Ser, De, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Method {
+    #[default]
+    Post,
+    Put,
+    Patch,
+}
+
+/// How the image is sent
+#[derive(Ser, De, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RequestBody {
+    /// Multipart form with the file in `field`, and `fields`
+    #[default]
+    Multipart,
+    /// Image as the whole body, with its `Content-Type`
+    Binary,
+}
+
+/// Declarative definition of an image host, like custom uploaders of
+/// ShareX. Strings are templates of [`Custom`].
+///
+/// ```ron
+/// Custom((
+///     url: "https://example.com/upload",
+///     headers: {"Authorization": "Bearer xxx"},
+///     field: Some("image"),
+///     result_url: Some("{json:data.url}"),
+///     deletion_url: Some("{json:data.delete_url}"),
+/// ))
+/// ```
+#[derive(Ser, De, Debug, Clone, Default)]
+pub struct CustomUploader {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub method: Method,
+    pub url: String,
+    /// Query parameters
+    #[serde(default)]
+    pub parameters: BTreeMap<String, String>,
+    /// Headers, e.g. `Authorization`
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: RequestBody,
+    /// Name of the file field of multipart bodies [default: file]
+    #[serde(default)]
+    pub field: Option<String>,
+    /// Extra text fields of multipart bodies
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// URL of the uploaded image [default: `{response}`]
+    #[serde(default)]
+    pub result_url: Option<String>,
+    #[serde(default)]
+    pub thumbnail_url: Option<String>,
+    /// URL that deletes the image when visited
+    #[serde(default)]
+    pub deletion_url: Option<String>,
+    /// Message of failed uploads [default: the response]
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// ShareX custom uploader file, version 13 or earlier
+#[derive(De)]
+#[serde(rename_all = "PascalCase")]
+struct Sxcu {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    destination_type: Option<String>,
+    #[serde(default, alias = "RequestType")]
+    request_method: Option<String>,
+    #[serde(rename = "RequestURL")]
+    request_url: String,
+    #[serde(default)]
+    parameters: BTreeMap<String, String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    arguments: BTreeMap<String, String>,
+    #[serde(default)]
+    file_form_name: Option<String>,
+    /// Referred by `$regex:{index},{group}$` of versions before 13
+    #[serde(default)]
+    regex_list: Vec<String>,
+    #[serde(default, rename = "URL")]
+    url: Option<String>,
+    #[serde(default, rename = "ThumbnailURL")]
+    thumbnail_url: Option<String>,
+    #[serde(default, rename = "DeletionURL")]
+    deletion_url: Option<String>,
+    #[serde(default)]
+    error_message: Option<String>,
+}
+
+impl CustomUploader {
+    /// Import a ShareX custom uploader (`.sxcu`). Syntax of ShareX versions
+    /// before 13, like `$json:data.url$`, is converted.
+    pub fn from_sxcu(content: &str) -> Result<Self> {
+        let bad = |reason: String| ShotError::Config(format!("Unsupported .sxcu: {reason}"));
+        // Files saved by ShareX start with a BOM
+        let sxcu: Sxcu = serde_json::from_str(content.trim_start_matches('\u{feff}'))
+            .map_err(|e| ShotError::Config(format!("Bad .sxcu: {e}")))?;
+
+        if let Some(ref kind) = sxcu.destination_type {
+            if !kind.contains("ImageUploader") {
+                return Err(bad(format!("`{kind}` is not an image uploader")));
+            }
+        }
+        let method = match sxcu.request_method.as_deref().map(str::to_uppercase) {
+            None => Method::Post,
+            Some(method) => match method.as_str() {
+                "POST" => Method::Post,
+                "PUT" => Method::Put,
+                "PATCH" => Method::Patch,
+                _ => return Err(bad(format!("method `{method}`"))),
+            },
+        };
+        let body = match sxcu.body.as_deref() {
+            None | Some("MultipartFormData") => RequestBody::Multipart,
+            Some("Binary") => RequestBody::Binary,
+            Some(body) => return Err(bad(format!("body `{body}`"))),
+        };
+
+        let legacy = |s: String| modernize(&s, &sxcu.regex_list);
+        let legacy_map = |map: BTreeMap<String, String>| {
+            map.into_iter()
+                .map(|(k, v)| Ok((k, legacy(v)?)))
+                .collect::<Result<_>>()
+        };
+        Ok(Self {
+            name: sxcu.name,
+            method,
+            url: legacy(sxcu.request_url)?,
+            parameters: legacy_map(sxcu.parameters)?,
+            headers: legacy_map(sxcu.headers)?,
+            body,
+            field: sxcu.file_form_name,
+            fields: legacy_map(sxcu.arguments)?,
+            result_url: sxcu.url.map(legacy).transpose()?,
+            thumbnail_url: sxcu.thumbnail_url.map(legacy).transpose()?,
+            deletion_url: sxcu.deletion_url.map(legacy).transpose()?,
+            error_message: sxcu.error_message.map(legacy).transpose()?,
+        })
+    }
+}
+
+/// Images uploaded to an HTTP endpoint described by a [`CustomUploader`].
+/// Only uploading is supported.
+///
+/// Strings of the definition are templates. Placeholders are `{filename}`,
+/// `{id}` (custom ID or file name) and `{meta}` (metadata as JSON),
+/// percent-encoded in `url`, and in result URLs and error messages,
+/// `{response}`, `{json:path}` (e.g. `{json:files[0].url}`),
+/// `{regex:pattern|group}` and `{header:name}` of the response. `\` escapes
+/// `{`, `}`, `|` and itself.
+///
+/// Custom IDs and metadata are only sent where `{id}` and `{meta}` are used.
+#[derive(Debug)]
+pub struct Custom {
+    uploader: CustomUploader,
+    session: attohttpc::Session,
+    retry: Retry,
+    limiter: RateLimiter,
+}
+
+/// Request parts of an upload, with templates evaluated
+struct Prepared {
+    url: Url,
+    parameters: Vec<(String, String)>,
+    headers: HeaderMap,
+    fields: Vec<(String, String)>,
+}
+
+impl Custom {
+    pub fn new(uploader: CustomUploader, client: &Client, retry: Retry) -> Result<Self> {
+        Ok(Self {
+            uploader,
+            session: client.session()?,
+            limiter: RateLimiter::new(retry.rate_limit),
+            retry,
+        })
+    }
+
+    fn name(&self) -> &str {
+        self.uploader.name.as_deref().unwrap_or("Custom")
+    }
+
+    /// Whether any request template has placeholder `name`
+    fn uses(&self, name: &str) -> bool {
+        let uploader = &self.uploader;
+        [&uploader.parameters, &uploader.headers, &uploader.fields]
+            .into_iter()
+            .flat_map(|map| map.values())
+            .chain([&uploader.url])
+            .filter_map(|template| parse(template).ok())
+            .flatten()
+            .any(|part| matches!(part, Part::Placeholder(ref x, _) if x == name))
+    }
+
+    fn prepare(&self, draft: &Draft) -> Result<Prepared> {
+        let ctx = Context::request(draft);
+        let eval_map = |map: &BTreeMap<String, String>| {
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), ctx.eval(v)?)))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        let url = ctx.eval_url(&self.uploader.url)?;
+        let url = Url::parse(&url)
+            .map_err(|e| ShotError::Config(format!("Bad URL `{url}` of custom uploader: {e}")))?;
+        let headers = eval_map(&self.uploader.headers)?
+            .into_iter()
+            .map(|(k, v)| {
+                let bad = |e: &dyn std::fmt::Display| {
+                    ShotError::Config(format!("Bad header `{k}` of custom uploader: {e}"))
+                };
+                Ok((
+                    HeaderName::try_from(&k).map_err(|e| bad(&e))?,
+                    HeaderValue::try_from(&v).map_err(|e| bad(&e))?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Prepared {
+            url,
+            parameters: eval_map(&self.uploader.parameters)?,
+            headers,
+            fields: eval_map(&self.uploader.fields)?,
+        })
+    }
+
+    fn request(&self, prepared: &Prepared) -> RequestBuilder {
+        let url = &prepared.url;
+        let mut req = match self.uploader.method {
+            Method::Post => self.session.post(url),
+            Method::Put => self.session.put(url),
+            Method::Patch => self.session.patch(url),
+        }
+        .params(&prepared.parameters);
+        for (k, v) in &prepared.headers {
+            req = req.header(k, v.clone());
+        }
+        req
+    }
+
+    /// Evaluate optional URL template `template` against the response
+    fn optional_url(ctx: &Context, template: &Option<String>, what: &str) -> Option<Url> {
+        let url = match template.as_deref().map(|x| ctx.eval(x)) {
+            None => return None,
+            Some(Ok(url)) if url.trim().is_empty() => return None,
+            Some(Ok(url)) => url,
+            Some(Err(e)) => {
+                warn!("Failed to get {what}: {e}");
+                return None;
+            }
+        };
+        Url::parse(url.trim())
+            .map_err(|e| warn!("Bad {what} `{url}`: {e}"))
+            .ok()
+    }
+}
+
+impl Backend for Custom {
+    fn validate(&self, draft: &Draft) -> Result<()> {
+        self.prepare(draft).map(drop)
+    }
+
+    /// Upload the image. It's only retried when the request is surely not
+    /// received, as the endpoint may not be idempotent.
+    fn upload(&self, draft: &Draft) -> Result<Image> {
+        let prepared = self.prepare(draft)?;
+        debug!("Uploading to {}", prepared.url);
+        if let Some(id) = draft.id.filter(|_| !self.uses("id")) {
+            warn!(
+                "{} doesn't use `{{id}}`, so ID `{id}` is not sent",
+                self.name()
+            );
+        }
+        let meta_sent = self.uses("meta");
+        if !meta_sent && !draft.meta.is_empty() {
+            warn!(
+                "{} doesn't use `{{meta}}`, so metadata is not sent",
+                self.name()
+            );
+        }
+
+        let res = match self.uploader.body {
+            RequestBody::Multipart => {
+                let mut fields = vec![Field::File {
+                    name: self.uploader.field.as_deref().unwrap_or("file"),
+                    filename: draft.filename,
+                    content_type: draft.content_type,
+                    content: &draft.content,
+                }];
+                fields.extend(
+                    prepared
+                        .fields
+                        .iter()
+                        .map(|(k, v)| Field::Text(k, v.clone())),
+                );
+                self.retry.send(&self.limiter, false, || {
+                    Ok(self
+                        .request(&prepared)
+                        .body(Form::new(&fields, draft.progress)))
+                })?
+            }
+            RequestBody::Binary => self.retry.send(&self.limiter, false, || {
+                Ok(self
+                    .request(&prepared)
+                    .try_header(CONTENT_TYPE, draft.content_type)
+                    .map_err(|_| {
+                        ShotError::Backend(format!("Bad content type `{}`", draft.content_type))
+                    })?
+                    .body(Raw {
+                        content: &draft.content,
+                        progress: draft.progress,
+                    }))
+            })?,
+        };
+
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = res.text()?;
+        debug!("Response ({status}): {body}");
+        let ctx = Context {
+            response: Some((&body, &headers)),
+            ..Context::request(draft)
+        };
+
+        if !status.is_success() {
+            let message = match self.uploader.error_message {
+                Some(ref template) => ctx.eval(template).unwrap_or_else(|_| body.clone()),
+                None => body.clone(),
+            };
+            return Err(ShotError::Status {
+                status: status.as_u16(),
+                body: message,
+            });
+        }
+
+        let url = ctx.eval(self.uploader.result_url.as_deref().unwrap_or("{response}"))?;
+        let url = Url::parse(url.trim()).map_err(|e| {
+            ShotError::Backend(format!(
+                "Bad URL `{url}` from response: {e}. Response: {body}"
+            ))
+        })?;
+        let thumbnail = Self::optional_url(&ctx, &self.uploader.thumbnail_url, "thumbnail URL");
+        let deletion_url = Self::optional_url(&ctx, &self.uploader.deletion_url, "deletion URL");
+
+        let id = url
+            .path_segments()
+            .and_then(|mut x| x.next_back())
+            .filter(|x| !x.is_empty())
+            .unwrap_or(draft.filename)
+            .to_owned();
+        Ok(Image {
+            id,
+            filename: draft.filename.to_owned(),
+            require_signed_urls: false,
+            uploaded: SystemTime::now(),
+            variants: [url].into_iter().chain(thumbnail).collect(),
+            meta: meta_sent.then(|| {
+                draft
+                    .meta
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.clone()))
+                    .collect()
+            }),
+            deletion_url,
+        })
+    }
+
+    fn get(&self, _: &str) -> Result<Image> {
+        Err(unsupported(self.name(), "getting images"))
+    }
+
+    fn list(&self, _: u32, _: u32) -> Result<Vec<Image>> {
+        Err(unsupported(self.name(), "listing images"))
+    }
+
+    fn delete(&self, _: &str) -> Result<()> {
+        Err(unsupported(
+            self.name(),
+            "deleting images, use the deletion URL",
+        ))
+    }
+}
+
+/// What templates are evaluated with
+struct Context<'a> {
+    filename: &'a str,
+    id: Option<&'a str>,
+    meta: Option<&'a HashMap<&'a str, Value>>,
+    response: Option<(&'a str, &'a HeaderMap)>,
+    json: OnceCell<Option<Value>>,
+}
+
+impl<'a> Context<'a> {
+    fn new(filename: &'a str, response: Option<(&'a str, &'a HeaderMap)>) -> Self {
+        Self {
+            filename,
+            id: None,
+            meta: None,
+            response,
+            json: OnceCell::new(),
+        }
+    }
+
+    /// Context of the request uploading `draft`
+    fn request(draft: &'a Draft) -> Self {
+        Self {
+            id: draft.id,
+            meta: Some(&draft.meta),
+            ..Self::new(draft.filename, None)
+        }
+    }
+
+    fn eval(&self, template: &str) -> Result<String> {
+        self.render(template, false)
+    }
+
+    /// Evaluate URL template `template`, with placeholders percent-encoded
+    fn eval_url(&self, template: &str) -> Result<String> {
+        self.render(template, true)
+    }
+
+    fn render(&self, template: &str, url: bool) -> Result<String> {
+        /// Characters kept as is in URLs
+        const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+            .remove(b'-')
+            .remove(b'_')
+            .remove(b'.')
+            .remove(b'~');
+
+        let mut out = String::new();
+        for part in parse(template)? {
+            match part {
+                Part::Text(text) => out.push_str(&text),
+                Part::Placeholder(name, args) => {
+                    let value = self.placeholder(&name, &args)?;
+                    match url {
+                        true => out.extend(utf8_percent_encode(&value, UNRESERVED)),
+                        false => out.push_str(&value),
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn placeholder(&self, name: &str, args: &[String]) -> Result<String> {
+        let arg = || args.join("|");
+        match name {
+            "filename" => return Ok(self.filename.to_owned()),
+            "id" => return Ok(self.id.unwrap_or(self.filename).to_owned()),
+            // Map of JSON values is always serializable
+            "meta" => return Ok(serde_json::to_string(&self.meta).unwrap()),
+            _ => {}
+        }
+        let Some((body, headers)) = self.response else {
+            return Err(ShotError::Config(format!(
+                "`{{{name}}}` can't be used in requests of custom uploaders"
+            )));
+        };
+        match name {
+            "response" => Ok(body.to_owned()),
+            "header" => Ok(headers
+                .get(arg())
+                .and_then(|x| x.to_str().ok())
+                .unwrap_or_default()
+                .to_owned()),
+            "json" => {
+                let json = self.json.get_or_init(|| serde_json::from_str(body).ok());
+                let value = json
+                    .as_ref()
+                    .ok_or_else(|| ShotError::Backend(format!("Response is not JSON: {body}")))?;
+                match json_path(value, &arg()) {
+                    Some(Value::String(s)) => Ok(s.clone()),
+                    Some(Value::Null) | None => Err(ShotError::Backend(format!(
+                        "Nothing at `{}` in response: {body}",
+                        arg()
+                    ))),
+                    Some(value) => Ok(value.to_string()),
+                }
+            }
+            "regex" => {
+                // The last argument is the group if it looks like one, as
+                // patterns may contain `|` too
+                let (pattern, group) = match args.split_last() {
+                    Some((group, rest))
+                        if !rest.is_empty()
+                            && !group.is_empty()
+                            && group.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+                    {
+                        (rest.join("|"), group.as_str())
+                    }
+                    _ => (arg(), "0"),
+                };
+                let regex = Regex::new(&pattern).map_err(|e| {
+                    ShotError::Config(format!("Bad regex `{pattern}` of custom uploader: {e}"))
+                })?;
+                let captures = regex.captures(body).ok_or_else(|| {
+                    ShotError::Backend(format!("`{pattern}` not found in response: {body}"))
+                })?;
+                let matched = match group.parse::<usize>() {
+                    Ok(i) => captures.get(i),
+                    Err(_) => captures.name(group),
+                };
+                Ok(matched.map(|x| x.as_str()).unwrap_or_default().to_owned())
+            }
+            name => Err(ShotError::Config(format!(
+                "`{{{name}}}` is not supported in custom uploaders"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(String, Vec<String>),
+}
+
+/// Split `template` into text and `{name:arg|arg}` placeholders. Braces
+/// inside a placeholder are kept if balanced, e.g. `{regex:\d{3}}`.
+fn parse(template: &str) -> Result<Vec<Part>> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.push_str(&unescape(chars.next())),
+            '{' => {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+                // Name, then arguments
+                let mut fields = vec![String::new()];
+                let mut depth = 0;
+                loop {
+                    let c = chars.next().ok_or_else(|| {
+                        ShotError::Config(format!("Unclosed `{{` in template `{template}`"))
+                    })?;
+                    let args = fields.len() > 1;
+                    let field = fields.last_mut().unwrap();
+                    match c {
+                        '\\' => field.push_str(&unescape(chars.next())),
+                        '}' if depth == 0 => break,
+                        ':' if !args => fields.push(String::new()),
+                        '|' if depth == 0 && args => fields.push(String::new()),
+                        c => {
+                            match c {
+                                '{' => depth += 1,
+                                '}' => depth -= 1,
+                                _ => {}
+                            }
+                            field.push(c)
+                        }
+                    }
+                }
+                let name = fields.remove(0);
+                parts.push(Part::Placeholder(name, fields));
+            }
+            c => text.push(c),
+        }
+    }
+    parts.push(Part::Text(text));
+    Ok(parts)
+}
+
+/// Character after `\\`. Only special characters are escaped, so patterns
+/// like `\\d` are kept as is.
+fn unescape(next: Option<char>) -> String {
+    match next {
+        Some(c @ ('{' | '}' | '|' | '\\')) => c.to_string(),
+        Some(c) => format!("\\{c}"),
+        None => "\\".to_owned(),
+    }
+}
+
+/// Value at `path` like `data.files[0].url`, with optional leading `$.`.
+/// Keys with dots can be quoted in brackets, e.g. `['a.b']`.
+fn json_path<'v>(value: &'v Value, path: &str) -> Option<&'v Value> {
+    let mut rest = path.trim().trim_start_matches('$');
+    let mut cur = value;
+    while !rest.is_empty() {
+        if let Some(bracket) = rest.strip_prefix('[') {
+            let (inner, after) = bracket.split_once(']')?;
+            let key = inner.trim_matches(['\'', '"']);
+            cur = match key.parse::<usize>() {
+                Ok(i) if key == inner => cur.get(i)?,
+                _ => cur.get(key)?,
+            };
+            rest = after;
+        } else {
+            let seg = rest.strip_prefix('.').unwrap_or(rest);
+            let (key, after) = seg.split_at(seg.find(['.', '[']).unwrap_or(seg.len()));
+            if !key.is_empty() {
+                cur = cur.get(key)?;
+            }
+            rest = after;
+        }
+    }
+    Some(cur)
+}
+
+/// Convert `$name:arg$` syntax of ShareX before version 13 to `{name:arg}`
+fn modernize(template: &str, regex_list: &[String]) -> Result<String> {
+    let escape = |s: &str| {
+        s.chars().fold(String::new(), |mut out, c| {
+            if matches!(c, '{' | '}' | '|' | '\\') {
+                out.push('\\');
+            }
+            out.push(c);
+            out
+        })
+    };
+    // Templates of version 13 and later don't use `$`, or are in URLs
+    if !template.contains("$json:")
+        && !template.contains("$regex:")
+        && !template.contains("$response$")
+        && !template.contains("$header:")
+        && !template.contains("$filename$")
+    {
+        return Ok(template.to_owned());
+    }
+
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        let Some(len) = rest[start + 1..].find('$') else {
+            break;
+        };
+        out.push_str(&escape(&rest[..start]));
+        let inner = &rest[start + 1..start + 1 + len];
+        let (name, arg) = inner.split_once(':').unwrap_or((inner, ""));
+        match name {
+            "" => out.push('$'),
+            "response" | "filename" => out.push_str(&format!("{{{name}}}")),
+            "json" | "header" => out.push_str(&format!("{{{name}:{}}}", escape(arg))),
+            "regex" => {
+                let (index, group) = arg.split_once(',').unwrap_or((arg, "0"));
+                let pattern = index
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| regex_list.get(i.checked_sub(1)?))
+                    .ok_or_else(|| {
+                        ShotError::Config(format!("Unsupported .sxcu: no regex {index}"))
+                    })?;
+                out.push_str(&format!("{{regex:{}|{group}}}", escape(pattern)))
+            }
+            name => {
+                return Err(ShotError::Config(format!(
+                    "Unsupported .sxcu: `${name}$` is not supported"
+                )))
+            }
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(&escape(rest));
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn text(s: &str) -> Part {
+        Part::Text(s.to_owned())
+    }
+
+    fn placeholder(name: &str, args: &[&str]) -> Part {
+        Part::Placeholder(
+            name.to_owned(),
+            args.iter().map(|x| x.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn parse_templates() {
+        for (template, parts) in [
+            ("plain", vec![text("plain")]),
+            (
+                "a/{filename}.b",
+                vec![text("a/"), placeholder("filename", &[]), text(".b")],
+            ),
+            (r"\{x\}\|\\", vec![text(r"{x}|\")]),
+            (r"\d", vec![text(r"\d")]),
+            (
+                "{json:data.url}",
+                vec![text(""), placeholder("json", &["data.url"]), text("")],
+            ),
+            (
+                "{header:a:b}",
+                vec![text(""), placeholder("header", &["a:b"]), text("")],
+            ),
+            (
+                r"{regex:\d{3}|1}",
+                vec![text(""), placeholder("regex", &[r"\d{3}", "1"]), text("")],
+            ),
+            (
+                r"{regex:(a\|b){1,2}}",
+                vec![text(""), placeholder("regex", &["(a|b){1,2}"]), text("")],
+            ),
+            (
+                r"{regex:a\}b|c|2}",
+                vec![text(""), placeholder("regex", &["a}b", "c", "2"]), text("")],
+            ),
+        ] {
+            assert_eq!(parse(template).unwrap(), parts, "{template}");
+        }
+        for bad in ["{json:a", "{regex:\\d{3}", "a{"] {
+            assert!(parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn json_paths() {
+        let value = json!({
+            "data": {"files": [{"url": "u"}], "n": 1, "list": [[1, 2]]},
+            "a.b": "dotted",
+        });
+        for (path, expected) in [
+            ("data.files[0].url", Some(json!("u"))),
+            ("$.data.files[0].url", Some(json!("u"))),
+            ("$data.n", Some(json!(1))),
+            ("data['files'][0][\"url\"]", Some(json!("u"))),
+            ("data.list[0][1]", Some(json!(2))),
+            ("['a.b']", Some(json!("dotted"))),
+            ("data.files[1]", None),
+            ("data.missing", None),
+            ("data.files[0", None),
+        ] {
+            assert_eq!(json_path(&value, path).cloned(), expected, "{path}");
+        }
+        assert_eq!(json_path(&value, "$"), Some(&value));
+    }
+
+    #[test]
+    fn modernize_legacy() {
+        let regexes = [r"id=(\w+)".to_owned(), "(?P<url>http[^\"]+)".to_owned()];
+        for (legacy, modern) in [
+            ("$json:data.url$", "{json:data.url}"),
+            ("$json:files[0].url$", "{json:files[0].url}"),
+            ("https://x/$response$", "https://x/{response}"),
+            ("$header:Location$", "{header:Location}"),
+            ("$filename$", "{filename}"),
+            ("$regex:1,1$", r"{regex:id=(\\w+)|1}"),
+            ("$regex:2,url$", "{regex:(?P<url>http[^\"]+)|url}"),
+            ("$regex:1$", r"{regex:id=(\\w+)|0}"),
+            ("{x} $response$", r"\{x\} {response}"),
+            ("$$ $response$", "$ {response}"),
+            // Version 13 templates are kept
+            ("{json:data.url}", "{json:data.url}"),
+            ("https://x/?a=$b", "https://x/?a=$b"),
+        ] {
+            assert_eq!(modernize(legacy, &regexes).unwrap(), modern, "{legacy}");
+        }
+        for bad in ["$regex:3,1$", "$regex:x$", "$random$ $response$"] {
+            assert!(modernize(bad, &regexes).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn eval_response() {
+        let mut headers = HeaderMap::new();
+        headers.insert("location", HeaderValue::from_static("https://x/l"));
+        let body = r#"{"data": {"id": "abc123", "n": 7}}"#;
+        let context = Context::new("a.png", Some((body, &headers)));
+        for (template, expected) in [
+            ("{filename}", "a.png"),
+            ("https://x/{json:data.id}.png", "https://x/abc123.png"),
+            ("{json:data.n}", "7"),
+            ("{header:Location}", "https://x/l"),
+            (r#"{regex:"id": "(\w{3})|1}"#, "abc"),
+            (r"{regex:(?P<id>\d+)|id}", "123"),
+            (
+                &modernize("$regex:1,1$", &[r#""id": "(\w+)""#.to_owned()]).unwrap(),
+                "abc123",
+            ),
+        ] {
+            assert_eq!(context.eval(template).unwrap(), expected, "{template}");
+        }
+        assert!(context.eval("{json:data.missing}").is_err());
+        assert!(Context::new("a.png", None).eval("{response}").is_err());
+    }
+
+    #[test]
+    fn eval_request() {
+        let draft = Draft {
+            filename: "a b#.png",
+            id: Some("docs/a"),
+            content: crate::Content::Bytes(b""),
+            content_type: "image/png",
+            require_signed_urls: false,
+            meta: HashMap::from([("k", json!("v"))]),
+            progress: None,
+        };
+        let context = Context::request(&draft);
+        assert_eq!(
+            context.eval_url("https://x/{filename}?id={id}").unwrap(),
+            "https://x/a%20b%23.png?id=docs%2Fa"
+        );
+        assert_eq!(context.eval("{id}/{filename}").unwrap(), "docs/a/a b#.png");
+        assert_eq!(context.eval("{meta}").unwrap(), r#"{"k":"v"}"#);
+        assert!(context.eval("{response}").is_err());
+    }
+}
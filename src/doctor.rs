@@ -0,0 +1,328 @@
+//! Diagnostic checks of `shot doctor` and `shot auth`, collected in a
+//! [`Report`]
+
+use std::{fmt::Display, io::Cursor, path::Path};
+
+use color_eyre::{eyre::eyre, owo_colors::OwoColorize, Result};
+use image::{
+    error::UnsupportedErrorKind, DynamicImage, ImageError, ImageFormat, ImageOutputFormat,
+};
+use serde::Serialize as Ser;
+
+use crate::{
+    display_aligned, display_title, readable_by_others, ApiError, Config, Output, ProjectConfig,
+    ShotError, API,
+};
+
+/// Formats `image` may be built with
+const FORMATS: &[ImageFormat] = &[
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Gif,
+    ImageFormat::WebP,
+    ImageFormat::Pnm,
+    ImageFormat::Tiff,
+    ImageFormat::Tga,
+    ImageFormat::Dds,
+    ImageFormat::Bmp,
+    ImageFormat::Ico,
+    ImageFormat::Hdr,
+    ImageFormat::OpenExr,
+    ImageFormat::Farbfeld,
+    ImageFormat::Avif,
+];
+
+/// Outcome of a single check
+#[derive(Ser, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Ser, Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+/// List of checks with their outcome, in the order they were run
+#[derive(Ser, Debug, Clone, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn push(
+        &mut self,
+        name: impl Into<String>,
+        status: Status,
+        detail: impl Into<String>,
+        hint: Option<String>,
+    ) {
+        self.checks.push(Check {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+            hint,
+        })
+    }
+
+    pub fn pass(&mut self, name: impl Into<String>, detail: impl Into<String>) {
+        self.push(name, Status::Pass, detail, None)
+    }
+
+    pub fn warn(
+        &mut self,
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        hint: Option<String>,
+    ) {
+        self.push(name, Status::Warn, detail, hint)
+    }
+
+    pub fn fail(
+        &mut self,
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        hint: Option<String>,
+    ) {
+        self.push(name, Status::Fail, detail, hint)
+    }
+
+    /// Config file location, validity and permissions, and project config
+    pub fn check_config(&mut self, path: &Path) {
+        if !path.exists() {
+            self.warn(
+                "Config",
+                format!("{} does not exist", path.display()),
+                Some("Run `shot auth` to create one, unless auth comes from env vars".to_owned()),
+            );
+        } else if let Err(e) = Config::from_dir(path) {
+            self.fail("Config", format!("{e:#}"), None);
+        } else if readable_by_others(path) {
+            self.warn(
+                "Config",
+                format!("{} is accessible by other users", path.display()),
+                Some(format!("Run `chmod 600 {}`", path.display())),
+            );
+        } else {
+            self.pass("Config", path.display().to_string());
+        }
+
+        match ProjectConfig::discover_path() {
+            Ok(None) => self.pass("Project config", "none"),
+            Ok(Some(path)) => match ProjectConfig::from_path(&path) {
+                Ok(_) => self.pass("Project config", path.display().to_string()),
+                Err(e) => self.fail("Project config", format!("{e:#}"), None),
+            },
+            Err(e) => self.warn("Project config", format!("{e:#}"), None),
+        }
+    }
+
+    /// Network, token and image quota of the account. Write permission is
+    /// checked only if `write`, see [`API::diagnose`].
+    pub fn check_api(&mut self, api: &API, write: bool) {
+        match api.ping() {
+            Ok(time) => self.pass(
+                "Network",
+                format!("{} in {}ms", api.base(), time.as_millis()),
+            ),
+            Err(e) => {
+                let (detail, _) = Self::describe(&e);
+                return self.fail(
+                    "Network",
+                    detail,
+                    Some("Check your connection and proxy settings".to_owned()),
+                );
+            }
+        }
+
+        self.checks.extend(api.diagnose(write).checks);
+
+        match api.stats() {
+            Ok(stats) => {
+                let count = stats.count;
+                let detail = format!("{} of {} images used", count.current, count.allowed);
+                if count.current >= count.allowed {
+                    self.fail(
+                        "Quota",
+                        detail,
+                        Some("Delete images or upgrade the plan".to_owned()),
+                    )
+                } else if count.current * 10 >= count.allowed * 9 {
+                    self.warn("Quota", detail, None)
+                } else {
+                    self.pass("Quota", detail)
+                }
+            }
+            Err(e) => self.warn_error("Quota", &e),
+        }
+    }
+
+    /// Whether clipboard can be opened, and whether it has an image in it
+    #[cfg(feature = "clipboard")]
+    pub fn check_clipboard(&mut self) {
+        let mut cb = match arboard::Clipboard::new() {
+            Ok(cb) => cb,
+            Err(e) => {
+                let hint = cfg!(target_os = "linux").then(|| {
+                    "A running X11 or Wayland session is needed, check `$DISPLAY` or \
+                     `$WAYLAND_DISPLAY`"
+                        .to_owned()
+                });
+                return self.fail("Clipboard", e.to_string(), hint);
+            }
+        };
+        match cb.get_image() {
+            Ok(img) => self.pass(
+                "Clipboard",
+                format!("has an image of {} x {}", img.width, img.height),
+            ),
+            Err(arboard::Error::ContentNotAvailable) => self.warn(
+                "Clipboard",
+                "no image in clipboard",
+                Some("Copy an image, not a file of image, and try again".to_owned()),
+            ),
+            Err(e) => self.fail("Clipboard", e.to_string(), None),
+        }
+    }
+
+    /// Image formats supported by this build, for `shot upload`
+    pub fn check_formats(&mut self) {
+        let name = |fmt: &ImageFormat| format!("{fmt:?}");
+        let read = FORMATS
+            .iter()
+            .filter(|fmt| can_read(**fmt))
+            .map(name)
+            .collect::<Vec<_>>();
+        let write = FORMATS
+            .iter()
+            .filter(|fmt| can_write(**fmt))
+            .map(name)
+            .collect::<Vec<_>>();
+        self.pass("Decode formats", read.join(", "));
+        self.pass("Encode formats", write.join(", "));
+    }
+
+    /// Record a warning from an error, with hint if there's any
+    pub fn warn_error(&mut self, name: impl Into<String>, err: &ShotError) {
+        let (detail, hint) = Self::describe(err);
+        self.warn(name, detail, hint)
+    }
+
+    /// Record a failure from an error, with hint if there's any
+    pub fn fail_error(&mut self, name: impl Into<String>, err: &ShotError) {
+        let (detail, hint) = Self::describe(err);
+        self.fail(name, detail, hint)
+    }
+
+    fn describe(err: &ShotError) -> (String, Option<String>) {
+        match err {
+            ShotError::Api { errors, .. } if !errors.is_empty() => (
+                errors
+                    .iter()
+                    .map(|e| format!("{} (code {})", e.message, e.code))
+                    .collect::<Vec<_>>()
+                    .join("; "),
+                errors
+                    .iter()
+                    .find_map(ApiError::hint)
+                    .map(ToOwned::to_owned),
+            ),
+            err => {
+                let mut detail = err.to_string();
+                let mut source = std::error::Error::source(err);
+                while let Some(e) = source {
+                    detail += &format!(": {e}");
+                    source = e.source();
+                }
+                (detail, None)
+            }
+        }
+    }
+
+    /// The worst status of all checks
+    pub fn status(&self) -> Status {
+        self.checks
+            .iter()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(Status::Pass)
+    }
+
+    /// Error if any check failed. Details are expected to be printed
+    /// already.
+    pub fn ensure_passed(&self) -> Result<()> {
+        match self
+            .checks
+            .iter()
+            .filter(|c| c.status == Status::Fail)
+            .count()
+        {
+            0 => Ok(()),
+            1 => Err(eyre!("1 check failed")),
+            n => Err(eyre!("{} checks failed", n)),
+        }
+    }
+
+    pub fn print(&self, output: Output) -> Result<()> {
+        match output {
+            Output::Json => println!("{}", serde_json::to_string_pretty(self)?),
+            Output::Human => {
+                let space = self
+                    .checks
+                    .iter()
+                    .map(|c| c.name.len())
+                    .max()
+                    .unwrap_or_default();
+                display_title("Doctor", space);
+                for check in &self.checks {
+                    display_aligned(
+                        &check.name,
+                        &format!("{} {}", check.status, check.detail),
+                        space,
+                    );
+                    if let Some(ref hint) = check.hint {
+                        display_aligned("", &hint.dimmed().to_string(), space);
+                    }
+                }
+                println!();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decoding nothing fails either way, but only an unsupported format fails
+/// with `Unsupported`
+fn can_read(fmt: ImageFormat) -> bool {
+    !matches!(
+        image::load_from_memory_with_format(&[], fmt),
+        Err(ImageError::Unsupported(e)) if matches!(e.kind(), UnsupportedErrorKind::Format(_))
+    )
+}
+
+fn can_write(fmt: ImageFormat) -> bool {
+    match ImageOutputFormat::from(fmt) {
+        ImageOutputFormat::Unsupported(_) => false,
+        // Some formats are recognized but have no encoder compiled in
+        out => DynamicImage::new_rgba8(1, 1)
+            .write_to(&mut Cursor::new(vec![]), out)
+            .is_ok(),
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Pass => write!(f, "{}", "PASS".green().bold()),
+            Status::Warn => write!(f, "{}", "WARN".yellow().bold()),
+            Status::Fail => write!(f, "{}", "FAIL".red().bold()),
+        }
+    }
+}
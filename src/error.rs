@@ -0,0 +1,158 @@
+use std::{io, path::PathBuf, process::ExitCode};
+
+use crate::ApiError;
+
+/// Everything that can go wrong when talking to Cloudflare or preparing an
+/// image, for callers that need to tell failures apart
+#[derive(Debug, thiserror::Error)]
+pub enum ShotError {
+    /// Credentials are missing or rejected
+    #[error("{0}")]
+    Auth(String),
+
+    /// Request did not get a response, e.g. DNS, connection or TLS failure
+    #[error("Failed to request API")]
+    Network(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// Non-success status without a Cloudflare error body, e.g. from a proxy
+    #[error("Server responded {status}: {body}")]
+    Status { status: u16, body: String },
+
+    /// Cloudflare returned `success: false`, regardless of HTTP status
+    #[error("{}", format_api_errors(.errors))]
+    Api {
+        status: Option<u16>,
+        errors: Vec<ApiError>,
+    },
+
+    /// Successful status with a body that is not the expected JSON
+    #[error("Unexpected response ({status}): {body}")]
+    InvalidResponse {
+        status: u16,
+        body: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("Unable to decode image")]
+    Decode(#[source] image::ImageError),
+
+    #[error("Unable to encode image")]
+    Encode(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// Something exceeds a Cloudflare limit, checked before sending
+    #[error("{what} is {size} bytes, larger than the {limit} bytes limit")]
+    TooLarge {
+        what: &'static str,
+        size: usize,
+        limit: usize,
+    },
+
+    #[cfg(feature = "clipboard")]
+    #[error("Failed to access clipboard")]
+    Clipboard(#[source] arboard::Error),
+
+    /// Config, flags or environment are invalid
+    #[error("{0}")]
+    Config(String),
+
+    /// A storage backend rejected or doesn't support the operation
+    #[error("{0}")]
+    Backend(String),
+
+    /// A storage backend can't do the operation at all, e.g. listing images
+    /// of a custom uploader
+    #[error("{backend} backend does not support {op}")]
+    Unsupported { backend: String, op: String },
+
+    #[error("Failed to access {}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl ShotError {
+    /// Process exit code, following `sysexits.h`
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            ShotError::Auth(_) => 77,
+            ShotError::Api { .. } if self.is_auth() => 77,
+            ShotError::Network(_) => 75,
+            ShotError::Status { .. } | ShotError::Api { .. } if self.is_transient() => 75,
+            ShotError::Status { .. }
+            | ShotError::Api { .. }
+            | ShotError::InvalidResponse { .. } => 69,
+            ShotError::Decode(_) | ShotError::TooLarge { .. } => 65,
+            ShotError::Encode(_) => 70,
+            #[cfg(feature = "clipboard")]
+            ShotError::Clipboard(_) => 66,
+            ShotError::Config(_) => 78,
+            ShotError::Backend(_) | ShotError::Unsupported { .. } => 69,
+            ShotError::Io { .. } => 74,
+        })
+    }
+
+    /// Whether credentials are missing, invalid or lack permission
+    pub fn is_auth(&self) -> bool {
+        match self {
+            ShotError::Auth(_) => true,
+            ShotError::Api { status, errors } => {
+                matches!(status, Some(401 | 403))
+                    || errors
+                        .iter()
+                        .any(|e| matches!(e.code, 1000 | 6003 | 6111 | 9109 | 10000))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether trying again later may succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ShotError::Network(_) => true,
+            ShotError::Status { status, .. }
+            | ShotError::Api {
+                status: Some(status),
+                ..
+            } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Cloudflare error codes, empty if this is not an API error
+    pub fn codes(&self) -> Vec<u32> {
+        match self {
+            ShotError::Api { errors, .. } => errors.iter().map(|e| e.code).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl From<attohttpc::Error> for ShotError {
+    fn from(e: attohttpc::Error) -> Self {
+        ShotError::Network(e.into())
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<reqwest::Error> for ShotError {
+    fn from(e: reqwest::Error) -> Self {
+        ShotError::Network(e.into())
+    }
+}
+
+fn format_api_errors(errors: &[ApiError]) -> String {
+    if errors.is_empty() {
+        return "API returned an error without message".to_owned();
+    }
+    errors
+        .iter()
+        .map(|e| match e.hint() {
+            Some(hint) => format!("{} (code {}). {}", e.message, e.code, hint),
+            None => format!("{} (code {})", e.message, e.code),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
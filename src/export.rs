@@ -0,0 +1,457 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::ValueEnum;
+use log::{debug, info, warn};
+use serde::{Deserialize as De, Serialize as Ser};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{Image, ShotError, API};
+
+type Result<T, E = ShotError> = std::result::Result<T, E>;
+
+/// Format of the manifest written by [`Export`]
+#[derive(Ser, De, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    #[default]
+    Json,
+    Ron,
+}
+
+impl ManifestFormat {
+    fn file_name(self) -> &'static str {
+        match self {
+            ManifestFormat::Json => "manifest.json",
+            ManifestFormat::Ron => "manifest.ron",
+        }
+    }
+}
+
+/// Images of an exported account, stored as `manifest.json` or
+/// `manifest.ron` next to the downloaded files
+#[derive(Ser, De, Debug, Clone, Default)]
+pub struct Manifest {
+    pub account_id: String,
+    /// Variant downloaded, or `None` for original files
+    pub variant: Option<String>,
+    pub images: Vec<Exported>,
+}
+
+/// An exported image, with attributes needed to upload it again
+#[derive(Ser, De, Debug, Clone)]
+pub struct Exported {
+    pub id: String,
+    pub filename: String,
+    pub meta: Option<HashMap<String, Value>>,
+    #[serde(rename = "requireSignedURLs")]
+    pub require_signed_urls: bool,
+    #[serde(with = "humantime_serde")]
+    pub uploaded: SystemTime,
+    /// Path of the downloaded file, relative to the manifest
+    pub file: PathBuf,
+    pub size: u64,
+    /// Hex encoded SHA-256 of the file
+    pub sha256: String,
+}
+
+impl Manifest {
+    /// Read manifest in `dir`, in either format. `None` if there is none.
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        for format in [ManifestFormat::Json, ManifestFormat::Ron] {
+            let path = dir.join(format.file_name());
+            match Self::parse(&path, format) {
+                Err(ShotError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {}
+                manifest => return manifest.map(Some),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read manifest at `path`, either a manifest file or a directory with
+    /// one. Returns it with the directory its files are relative to.
+    pub fn open(path: &Path) -> Result<(Self, PathBuf)> {
+        if path.is_dir() {
+            let manifest = Self::read(path)?
+                .ok_or_else(|| ShotError::Config(format!("No manifest in {}", path.display())))?;
+            return Ok((manifest, path.to_owned()));
+        }
+        let format = match path.extension().and_then(|x| x.to_str()) {
+            Some("ron") => ManifestFormat::Ron,
+            _ => ManifestFormat::Json,
+        };
+        let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+        Ok((Self::parse(path, format)?, dir))
+    }
+
+    fn parse(path: &Path, format: ManifestFormat) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+        let manifest = match format {
+            ManifestFormat::Json => serde_json::from_str(&content).map_err(|e| e.to_string()),
+            ManifestFormat::Ron => ron::from_str(&content).map_err(|e| e.to_string()),
+        };
+        manifest.map_err(|e| ShotError::Config(format!("Bad manifest {}: {e}", path.display())))
+    }
+
+    /// Write into `dir` atomically, so an interrupted export never leaves a
+    /// broken manifest
+    pub fn write(&self, dir: &Path, format: ManifestFormat) -> Result<()> {
+        let path = dir.join(format.file_name());
+        // Manifests are always serializable
+        let content = match format {
+            ManifestFormat::Json => serde_json::to_string_pretty(self).unwrap(),
+            ManifestFormat::Ron => ron::ser::to_string_pretty(self, Default::default()).unwrap(),
+        };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|e| io_error(&path, e))?;
+
+        // Or it would be read by the next run instead
+        let other = match format {
+            ManifestFormat::Json => ManifestFormat::Ron,
+            ManifestFormat::Ron => ManifestFormat::Json,
+        };
+        let other = dir.join(other.file_name());
+        match fs::remove_file(&other) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(io_error(&other, e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Outcome of [`Export::run`]
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    pub downloaded: usize,
+    /// Already present with matching size and hash
+    pub skipped: usize,
+    /// IDs of images failed to download, with the reason
+    pub failed: Vec<(String, ShotError)>,
+}
+
+/// Export builder, downloading every image of an account into a directory
+/// with a [`Manifest`].
+///
+/// Exports are resumable: files already present with the size and hash in
+/// the manifest are skipped, and files are downloaded under a temporary
+/// name first, so a file that exists is always complete.
+pub struct Export<'a> {
+    api: &'a API,
+    dir: PathBuf,
+    variant: Option<String>,
+    format: ManifestFormat,
+    jobs: usize,
+    dry_run: bool,
+}
+
+impl<'a> Export<'a> {
+    /// Interval between saves of the manifest while downloading
+    const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+    pub fn new(api: &'a API, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            api,
+            dir: dir.into(),
+            variant: None,
+            format: Default::default(),
+            jobs: 4,
+            dry_run: false,
+        }
+    }
+
+    /// Download this variant instead of original files
+    pub fn variant(mut self, variant: Option<String>) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    pub fn format(mut self, format: ManifestFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Number of parallel downloads [default: 4]
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Only count what would be downloaded
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn run(&self) -> Result<ExportSummary> {
+        info!("Listing images...");
+        let images = self.api.list_all()?;
+        info!("Found {} images", images.len());
+
+        // Entries of a previous run of the same kind, to skip their files
+        let previous = Manifest::read(&self.dir)?
+            .filter(|x| x.variant == self.variant)
+            .map(|x| x.images)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|x| (x.id.clone(), x))
+            .collect::<HashMap<_, _>>();
+
+        let total = images.len();
+        let queue = Mutex::new(images.iter().zip(file_paths(&images)).enumerate());
+        let state = Mutex::new(Progress {
+            exported: vec![None; total],
+            summary: Default::default(),
+            saved: Instant::now(),
+        });
+
+        thread::scope(|s| {
+            for _ in 0..self.jobs.min(total) {
+                s.spawn(|| loop {
+                    let Some((i, (image, file))) = lock(&queue).next() else {
+                        break;
+                    };
+                    let outcome =
+                        file.and_then(|file| self.export(image, file, previous.get(&image.id)));
+                    let mut state = lock(&state);
+                    let done = state.summary.downloaded
+                        + state.summary.skipped
+                        + state.summary.failed.len()
+                        + 1;
+                    match outcome {
+                        Ok((exported, downloaded)) => {
+                            if downloaded && self.dry_run {
+                                state.summary.downloaded += 1;
+                                info!("[{done}/{total}] Would download {}", image.id);
+                            } else if downloaded {
+                                state.summary.downloaded += 1;
+                                info!(
+                                    "[{done}/{total}] Downloaded {} ({})",
+                                    image.id,
+                                    bytesize::to_string(exported.size, true)
+                                );
+                            } else {
+                                state.summary.skipped += 1;
+                                debug!("[{done}/{total}] Skipped {}", image.id);
+                            }
+                            state.exported[i] = Some(exported);
+                        }
+                        Err(e) => {
+                            warn!("[{done}/{total}] Failed to export {}: {e}", image.id);
+                            state.summary.failed.push((image.id.clone(), e));
+                        }
+                    }
+                    if !self.dry_run && state.saved.elapsed() > Self::SAVE_INTERVAL {
+                        if let Err(e) = self.save(&state.exported) {
+                            warn!("Failed to save manifest: {e}");
+                        }
+                        state.saved = Instant::now();
+                    }
+                });
+            }
+        });
+
+        let state = state.into_inner().unwrap_or_else(|e| e.into_inner());
+        if !self.dry_run {
+            self.save(&state.exported)?;
+        }
+        Ok(state.summary)
+    }
+
+    /// Download `image` into `file` unless `previous` export of it is
+    /// intact. Returns whether it's downloaded.
+    fn export(
+        &self,
+        image: &Image,
+        file: PathBuf,
+        previous: Option<&Exported>,
+    ) -> Result<(Exported, bool)> {
+        let path = self.dir.join(&file);
+        if let Some(previous) = previous.filter(|x| x.file == file) {
+            if intact(&path, previous.size, &previous.sha256) {
+                return Ok((previous.clone(), false));
+            }
+        }
+        let exported = |size, sha256| Exported {
+            id: image.id.clone(),
+            filename: image.filename.clone(),
+            meta: image.meta.clone(),
+            require_signed_urls: image.require_signed_urls,
+            uploaded: image.uploaded,
+            file: file.clone(),
+            size,
+            sha256,
+        };
+        if self.dry_run {
+            return Ok((exported(0, String::new()), true));
+        }
+
+        let url = match self.variant {
+            Some(ref name) => Some(
+                image
+                    .variant(name)
+                    .ok_or_else(|| ShotError::Backend(format!("Image has no variant `{name}`")))?,
+            ),
+            None => None,
+        };
+        if url.is_some() && image.require_signed_urls {
+            return Err(ShotError::Backend(
+                "Variants of images requiring signed URLs can't be downloaded, export original \
+                 files instead"
+                    .to_owned(),
+            ));
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
+        }
+        let part = path.with_extension(match path.extension() {
+            Some(ext) => format!("{}.part", ext.to_string_lossy()),
+            None => "part".to_owned(),
+        });
+        let file = File::create(&part).map_err(|e| io_error(&part, e))?;
+        let mut writer = Hashing {
+            inner: BufWriter::new(file),
+            hasher: Sha256::new(),
+        };
+        let written = match url {
+            Some(url) => self.api.download(url, &mut writer),
+            None => self.api.blob(&image.id, &mut writer),
+        };
+        let Hashing { inner, hasher } = writer;
+        let finished = written.and_then(|size| {
+            // Closes the file before renaming it
+            inner
+                .into_inner()
+                .map_err(|e| io_error(&part, e.into_error()))?;
+            fs::rename(&part, &path).map_err(|e| io_error(&path, e))?;
+            Ok(size)
+        });
+        match finished {
+            Ok(size) => Ok((exported(size, format!("{:x}", hasher.finalize())), true)),
+            Err(e) => {
+                let _ = fs::remove_file(&part);
+                Err(e)
+            }
+        }
+    }
+
+    fn save(&self, exported: &[Option<Exported>]) -> Result<()> {
+        fs::create_dir_all(&self.dir).map_err(|e| io_error(&self.dir, e))?;
+        Manifest {
+            account_id: self.api.account_id().to_owned(),
+            variant: self.variant.clone(),
+            images: exported.iter().flatten().cloned().collect(),
+        }
+        .write(&self.dir, self.format)
+    }
+}
+
+struct Progress {
+    /// In the order images are listed
+    exported: Vec<Option<Exported>>,
+    summary: ExportSummary,
+    saved: Instant,
+}
+
+/// Relative path of an exported image, `images/{id}` with extension of its
+/// file name
+fn file_path(image: &Image) -> Result<PathBuf> {
+    let mut path = PathBuf::from("images");
+    path.push(&image.id);
+    let valid = path.components().all(|c| matches!(c, Component::Normal(_)));
+    if !valid || image.id.ends_with('/') {
+        return Err(ShotError::Backend(format!(
+            "`{}` can't be used as a file name",
+            image.id
+        )));
+    }
+    if let Some(ext) = Path::new(&image.filename).extension() {
+        if path.extension() != Some(ext) {
+            path.as_mut_os_string().push(".");
+            path.as_mut_os_string().push(ext);
+        }
+    }
+    Ok(path)
+}
+
+/// Relative paths of `images`, see [`file_path`]. Paths shared by several
+/// images, ignoring case, or that are directories of other images get a hash
+/// of the ID, e.g. `images/a.0123456789abcdef.png`, so no two images share a
+/// file. It only depends on IDs, so resumed exports find the same files.
+fn file_paths(images: &[Image]) -> Vec<Result<PathBuf>> {
+    let paths = images.iter().map(file_path).collect::<Vec<_>>();
+    let key = |path: &Path| path.to_string_lossy().to_lowercase();
+    let mut files = HashMap::<String, usize>::new();
+    let mut dirs = HashSet::new();
+    for path in paths.iter().flatten() {
+        *files.entry(key(path)).or_default() += 1;
+        dirs.extend(path.ancestors().skip(1).map(key));
+    }
+    images
+        .iter()
+        .zip(paths)
+        .map(|(image, path)| {
+            let path = path?;
+            let key = key(&path);
+            if files[&key] == 1 && !dirs.contains(&key) {
+                return Ok(path);
+            }
+            let hash = format!("{:x}", Sha256::digest(image.id.as_bytes()));
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = match path.extension() {
+                Some(ext) => format!("{stem}.{}.{}", &hash[..16], ext.to_string_lossy()),
+                None => format!("{stem}.{}", &hash[..16]),
+            };
+            debug!("{} is taken by another image, using {name}", path.display());
+            Ok(path.with_file_name(name))
+        })
+        .collect()
+}
+
+/// Whether file at `path` has `size` and `sha256`
+pub(crate) fn intact(path: &Path, size: u64, sha256: &str) -> bool {
+    if fs::metadata(path).map(|x| x.len()).ok() != Some(size) {
+        return false;
+    }
+    let mut hasher = Sha256::new();
+    File::open(path)
+        .and_then(|mut file| io::copy(&mut file, &mut hasher))
+        .is_ok_and(|_| format!("{:x}", hasher.finalize()) == sha256)
+}
+
+/// Hashes everything written through it
+struct Hashing<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn io_error(path: &Path, source: io::Error) -> ShotError {
+    ShotError::Io {
+        path: path.to_owned(),
+        source,
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use image::ImageFormat;
+use log::{debug, info, warn};
+use serde_json::Value;
+
+use crate::{intact, mime_type, Backend, Content, Manifest, ShotError, Upload, API};
+
+type Result<T, E = ShotError> = std::result::Result<T, E>;
+
+/// Where [`Import`] takes images from
+pub enum ImportSource<'a> {
+    /// Original files written by [`Export`](crate::Export), relative to
+    /// `dir`. Exports of a variant are refused.
+    Manifest { manifest: Manifest, dir: PathBuf },
+    /// Original files of another Cloudflare account
+    Account(&'a API),
+}
+
+/// Outcome of [`Import::run`]
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub uploaded: usize,
+    /// Already in the backend with the same ID
+    pub skipped: usize,
+    /// IDs of images failed to upload, with the reason
+    pub failed: Vec<(String, ShotError)>,
+}
+
+/// Import builder, uploading images into a backend with their original IDs,
+/// file names, metadata and signed URL requirement. Upload time is not kept,
+/// as it's set by the backend.
+///
+/// Images whose ID is already in the backend are skipped, so an interrupted
+/// import can be run again. Backends that can't list images, e.g.
+/// [`Custom`](crate::Custom), get every image uploaded, and S3 backends must
+/// keep IDs as object keys.
+pub struct Import<'a> {
+    backend: &'a dyn Backend,
+    source: ImportSource<'a>,
+    dry_run: bool,
+}
+
+/// Attributes of an image to import, from either source
+struct Entry<'a> {
+    id: &'a str,
+    filename: &'a str,
+    meta: Option<&'a HashMap<String, Value>>,
+    require_signed_urls: bool,
+}
+
+impl<'a> Import<'a> {
+    pub fn new(backend: &'a dyn Backend, source: ImportSource<'a>) -> Self {
+        Self {
+            backend,
+            source,
+            dry_run: false,
+        }
+    }
+
+    /// Only count what would be uploaded
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn run(&self) -> Result<ImportSummary> {
+        let listed;
+        let entries = match self.source {
+            ImportSource::Manifest { ref manifest, .. } if manifest.variant.is_some() => {
+                return Err(ShotError::Config(format!(
+                    "Export has variant `{}` instead of original files, which can't be uploaded \
+                     as the originals. Export again without `--variant`",
+                    manifest.variant.as_deref().unwrap_or_default()
+                )));
+            }
+            ImportSource::Manifest { ref manifest, .. } => manifest
+                .images
+                .iter()
+                .map(|x| Entry {
+                    id: &x.id,
+                    filename: &x.filename,
+                    meta: x.meta.as_ref(),
+                    require_signed_urls: x.require_signed_urls,
+                })
+                .collect::<Vec<_>>(),
+            ImportSource::Account(api) => {
+                info!("Listing images of account {}...", api.account_id());
+                listed = api.list_all()?;
+                listed
+                    .iter()
+                    .map(|x| Entry {
+                        id: &x.id,
+                        filename: &x.filename,
+                        meta: x.meta.as_ref(),
+                        require_signed_urls: x.require_signed_urls,
+                    })
+                    .collect()
+            }
+        };
+        info!("Found {} images", entries.len());
+
+        self.backend.check_ids_kept()?;
+        // Checked before uploading anything, as Cloudflare rejects custom IDs
+        // of images requiring signed URLs
+        if let Some(entry) = entries.iter().find(|x| x.require_signed_urls) {
+            Upload::new(self.backend, entry.filename, Content::Bytes(&[]))
+                .id(entry.id)
+                .require_signed_urls()
+                .validate()?;
+        }
+        info!("Listing images already uploaded...");
+        let existing = existing_ids(self.backend)?;
+
+        let total = entries.len();
+        let mut summary = ImportSummary::default();
+        for (i, entry) in entries.iter().enumerate() {
+            let done = i + 1;
+            if existing.as_ref().is_some_and(|x| x.contains(entry.id)) {
+                summary.skipped += 1;
+                debug!("[{done}/{total}] Skipped {}, already uploaded", entry.id);
+                continue;
+            }
+            if self.dry_run {
+                summary.uploaded += 1;
+                info!("[{done}/{total}] Would upload {}", entry.id);
+                continue;
+            }
+            match self.import(entry, i) {
+                Ok(size) => {
+                    summary.uploaded += 1;
+                    info!(
+                        "[{done}/{total}] Uploaded {} ({})",
+                        entry.id,
+                        bytesize::to_string(size, true)
+                    );
+                }
+                // Uploaded since the backend was listed
+                Err(e) if e.codes().contains(&5409) => {
+                    summary.skipped += 1;
+                    debug!("[{done}/{total}] Skipped {}, already uploaded", entry.id);
+                }
+                Err(e) => {
+                    warn!("[{done}/{total}] Failed to import {}: {e}", entry.id);
+                    summary.failed.push((entry.id.to_owned(), e));
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Upload `entry`, the `i`th image of the source. Returns its size.
+    fn import(&self, entry: &Entry, i: usize) -> Result<u64> {
+        let bytes;
+        let (content, content_type) = match self.source {
+            ImportSource::Manifest {
+                ref manifest,
+                ref dir,
+            } => {
+                let exported = &manifest.images[i];
+                let path = dir.join(&exported.file);
+                if !intact(&path, exported.size, &exported.sha256) {
+                    return Err(ShotError::Backend(format!(
+                        "{} is missing, or changed since exported",
+                        path.display()
+                    )));
+                }
+                open(&path)?
+            }
+            ImportSource::Account(api) => {
+                let mut buf = vec![];
+                api.blob(entry.id, &mut buf)?;
+                bytes = buf;
+                let format = image::guess_format(&bytes).ok();
+                (Content::Bytes(&bytes), content_type(format))
+            }
+        };
+        let size = content.len();
+
+        let mut upload = Upload::new(self.backend, entry.filename, content)
+            .content_type(content_type)
+            .id(entry.id);
+        if entry.require_signed_urls {
+            upload = upload.require_signed_urls();
+        }
+        if let Some(meta) = entry.meta {
+            upload.extend_meta(meta.iter().map(|(k, v)| (k.as_str(), v.clone())));
+        }
+        upload.send()?;
+        Ok(size)
+    }
+}
+
+/// IDs of every image in `backend`, or `None` if it can't list images
+fn existing_ids(backend: &dyn Backend) -> Result<Option<HashSet<String>>> {
+    const PER_PAGE: u32 = 1000;
+    let mut ids = HashSet::new();
+    for page in 1.. {
+        let list = match backend.list(page, PER_PAGE) {
+            Ok(list) => list,
+            Err(e @ ShotError::Unsupported { .. }) => {
+                warn!("{e}, so images already uploaded can't be skipped");
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let last = list.len() < PER_PAGE as usize;
+        ids.extend(list.into_iter().map(|x| x.id));
+        if last {
+            break;
+        }
+    }
+    debug!("{} images already uploaded", ids.len());
+    Ok(Some(ids))
+}
+
+/// Stream the file at `path`, with its MIME type sniffed from the header
+fn open(path: &Path) -> Result<(Content<'static>, &'static str)> {
+    let error = |e: io::Error| ShotError::Io {
+        path: path.to_owned(),
+        source: e,
+    };
+    let mut file = File::open(path).map_err(error)?;
+    let len = file.metadata().map_err(error)?.len();
+    let mut header = vec![];
+    (&mut file)
+        .take(32)
+        .read_to_end(&mut header)
+        .and_then(|_| file.rewind())
+        .map_err(error)?;
+    let format = image::guess_format(&header)
+        .ok()
+        .or_else(|| ImageFormat::from_path(path).ok());
+    Ok((Content::reader(file, len), content_type(format)))
+}
+
+fn content_type(format: Option<ImageFormat>) -> &'static str {
+    format
+        .and_then(mime_type)
+        .unwrap_or("application/octet-stream")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{unsupported, Draft, Image};
+
+    /// Backend whose listing fails with `error`
+    struct Failing(fn() -> ShotError);
+
+    impl Backend for Failing {
+        fn upload(&self, _: &Draft) -> Result<Image> {
+            unimplemented!()
+        }
+
+        fn get(&self, _: &str) -> Result<Image> {
+            unimplemented!()
+        }
+
+        fn list(&self, _: u32, _: u32) -> Result<Vec<Image>> {
+            Err((self.0)())
+        }
+
+        fn delete(&self, _: &str) -> Result<()> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn existing_ids_of_unlistable_backend() {
+        let backend = Failing(|| unsupported("Custom", "listing images"));
+        assert!(existing_ids(&backend).unwrap().is_none());
+
+        let backend = Failing(|| ShotError::Backend("Bad XML".to_owned()));
+        assert!(matches!(existing_ids(&backend), Err(ShotError::Backend(_))));
+    }
+}
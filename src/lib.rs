@@ -0,0 +1,79 @@
+//! Upload images to [Cloudflare Images](https://www.cloudflare.com/products/cloudflare-images/).
+//!
+//! This is the library behind the `shot` CLI. It provides:
+//!
+//! - [`API`], a blocking client of Cloudflare Images, with retries and rate
+//!   limiting configured by [`Retry`] and HTTP settings by [`Client`].
+//!   `AsyncAPI` is its async counterpart, with the `async` feature
+//! - [`Upload`], a builder of a single upload, created by [`API::upload`].
+//!   Its [`Content`] can be streamed from a file instead of held in memory
+//! - [`Backend`], where images are stored: Cloudflare Images ([`API`]), a
+//!   local directory ([`Local`]), an S3 compatible bucket ([`S3`]) or any
+//!   HTTP endpoint described like a ShareX custom uploader ([`Custom`])
+//! - Models of API responses, e.g. [`Image`] and [`ApiError`], and a typed
+//!   [`ShotError`]
+//! - The image pipeline: [`Process`] redacts regions, draws watermarks and
+//!   captions, and encodes images into PNG, optionally optimized, and
+//!   [`Placeholder`] generates LQIP / BlurHash placeholders
+//! - [`Export`], downloading every image of an account with a [`Manifest`],
+//!   and [`Import`], uploading them again or copying them between accounts
+//! - [`Config`], the config file shared with the CLI
+//!
+//! ```no_run
+//! use shot::{Auth, Process, API};
+//!
+//! # fn main() -> color_eyre::Result<()> {
+//! let api = API::new(Auth {
+//!     account_id: "account id".to_owned(),
+//!     token: "api token".to_owned(),
+//! })?;
+//!
+//! let img = image::open("screenshot.png")?;
+//! let bytes = Process::default().encode(&img)?;
+//!
+//! let image = api.upload("screenshot.png", &bytes).id("docs/screenshot").send()?;
+//! println!("{}", image.variants[0]);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Features
+//!
+//! - `cli` (default): the `shot` binary
+//! - `clipboard` (enabled by `cli`): reading images from the system clipboard
+//! - `async`: `AsyncAPI`, an async client on tokio
+//! - `mock` (enabled by `cli`): `MockServer`, a local mock of Cloudflare
+//!   Images API for tests
+
+mod_use::mod_use![
+    api,
+    backend,
+    local,
+    custom,
+    s3,
+    multipart,
+    model,
+    error,
+    client,
+    retry,
+    config,
+    process,
+    optimize,
+    placeholder,
+    enrich,
+    export,
+    import,
+    util
+];
+
+pub mod doctor;
+
+#[cfg(feature = "async")]
+mod async_api;
+#[cfg(feature = "async")]
+pub use async_api::*;
+
+#[cfg(feature = "mock")]
+mod mock;
+#[cfg(feature = "mock")]
+pub use mock::*;
@@ -0,0 +1,204 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::{debug, warn};
+use url::Url;
+
+use crate::{Backend, Draft, Image, ShotError};
+
+type Result<T, E = ShotError> = std::result::Result<T, E>;
+
+/// Images stored as files in a directory, served from `base_url` by a web
+/// server.
+///
+/// ID of an image is its path relative to the directory, which is the custom
+/// ID or the file name. Attributes kept by Cloudflare, like metadata, are
+/// stored in `{file}.json` next to each image.
+#[derive(Debug, Clone)]
+pub struct Local {
+    dir: PathBuf,
+    base_url: Url,
+}
+
+impl Local {
+    const SIDECAR: &'static str = "json";
+
+    pub fn new(dir: impl Into<PathBuf>, mut base_url: Url) -> Self {
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        Self {
+            dir: dir.into(),
+            base_url,
+        }
+    }
+
+    /// Path of image `id`. IDs escaping the directory are rejected.
+    fn path(&self, id: &str) -> Result<PathBuf> {
+        let rel = Path::new(id);
+        let valid = !id.is_empty() && rel.components().all(|c| matches!(c, Component::Normal(_)));
+        if !valid {
+            return Err(ShotError::Backend(format!(
+                "`{id}` is not a valid ID for local storage"
+            )));
+        }
+        Ok(self.dir.join(rel))
+    }
+
+    fn sidecar(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(Self::SIDECAR);
+        PathBuf::from(name)
+    }
+
+    fn url(&self, id: &str) -> Result<Url> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| ShotError::Config(format!("Bad base URL `{}`", self.base_url)))?
+            .pop_if_empty()
+            .extend(id.split('/'));
+        Ok(url)
+    }
+
+    /// IDs of all images under `dir`, sorted
+    fn ids(&self, dir: &Path, out: &mut Vec<String>) -> Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && dir == self.dir => return Ok(()),
+            res => res.map_err(|e| io_error(dir, e))?,
+        };
+        for entry in entries {
+            let path = entry.map_err(|e| io_error(dir, e))?.path();
+            if path.is_dir() {
+                self.ids(&path, out)?;
+            } else if path.extension().map_or(true, |x| x != Self::SIDECAR) {
+                let rel = path.strip_prefix(&self.dir).unwrap_or(&path);
+                let id = rel
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                out.push(id);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Backend for Local {
+    fn upload(&self, draft: &Draft) -> Result<Image> {
+        let id = draft.id.unwrap_or(draft.filename);
+        let path = self.path(id)?;
+        if draft.require_signed_urls {
+            warn!("Signed URLs are not supported by local storage, image is public");
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
+        }
+
+        debug!("Writing image to {}", path.display());
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => {
+                    ShotError::Backend(format!("Image `{id}` already exists"))
+                }
+                _ => io_error(&path, e),
+            })?;
+        let mut writer = BufWriter::new(file);
+        let written = draft
+            .content
+            .write_to(&mut writer)
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            let _ = fs::remove_file(&path);
+            return Err(io_error(&path, e));
+        }
+
+        let image = Image {
+            id: id.to_owned(),
+            filename: draft.filename.to_owned(),
+            require_signed_urls: false,
+            uploaded: SystemTime::now(),
+            variants: vec![self.url(id)?],
+            meta: Some(
+                draft
+                    .meta
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.clone()))
+                    .collect(),
+            ),
+            deletion_url: None,
+        };
+        let sidecar = Self::sidecar(&path);
+        // Image is always serializable
+        fs::write(&sidecar, serde_json::to_vec_pretty(&image).unwrap())
+            .map_err(|e| io_error(&sidecar, e))?;
+        Ok(image)
+    }
+
+    fn get(&self, id: &str) -> Result<Image> {
+        let path = self.path(id)?;
+        let meta = fs::metadata(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => ShotError::Backend(format!("Image `{id}` not found")),
+            _ => io_error(&path, e),
+        })?;
+        let sidecar = Self::sidecar(&path);
+        if let Ok(file) = File::open(&sidecar) {
+            match serde_json::from_reader(file) {
+                Ok(image) => return Ok(image),
+                Err(e) => warn!("Ignoring bad {}: {e}", sidecar.display()),
+            }
+        }
+        // Copied into the directory by hand
+        Ok(Image {
+            id: id.to_owned(),
+            filename: path
+                .file_name()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            require_signed_urls: false,
+            uploaded: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            variants: vec![self.url(id)?],
+            meta: None,
+            deletion_url: None,
+        })
+    }
+
+    fn list(&self, page: u32, per_page: u32) -> Result<Vec<Image>> {
+        let mut ids = vec![];
+        self.ids(&self.dir, &mut ids)?;
+        ids.sort();
+        ids.iter()
+            .skip((page.max(1) - 1) as usize * per_page as usize)
+            .take(per_page as usize)
+            .map(|id| self.get(id))
+            .collect()
+    }
+
+    fn delete(&self, id: &str) -> Result<()> {
+        let path = self.path(id)?;
+        fs::remove_file(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => ShotError::Backend(format!("Image `{id}` not found")),
+            _ => io_error(&path, e),
+        })?;
+        let sidecar = Self::sidecar(&path);
+        match fs::remove_file(&sidecar) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(io_error(&sidecar, e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn io_error(path: &Path, source: io::Error) -> ShotError {
+    ShotError::Io {
+        path: path.to_owned(),
+        source,
+    }
+}
@@ -0,0 +1,49 @@
+use color_eyre::{config::HookBuilder, eyre::Context, Result};
+use env_logger::{
+    filter::Builder,
+    fmt::{Color, Style, StyledValue},
+};
+use log::Level;
+
+pub fn init_logger() -> Result<()> {
+    let mut builder = env_logger::Builder::new();
+
+    builder
+        .format(|f, record| {
+            use std::io::Write;
+
+            let mut style = f.style();
+            let level = colored_level(&mut style, record.level());
+
+            writeln!(f, " {}  {}", level, record.args())
+        })
+        .filter_level({
+            match ::std::env::var("SHOT_LOG") {
+                Ok(filter) => Builder::default().parse(&filter).build().filter(),
+                Err(_) => log::LevelFilter::Info,
+            }
+        })
+        .try_init()
+        .wrap_err("Failed to init logger")
+}
+
+fn colored_level(style: &mut Style, level: Level) -> StyledValue<'_, &'static str> {
+    match level {
+        Level::Trace => style
+            .set_bold(true)
+            .set_color(Color::Magenta)
+            .value("TRACE"),
+        Level::Debug => style.set_bold(true).set_color(Color::Blue).value("DEBUG"),
+        Level::Info => style.set_bold(true).set_color(Color::Green).value(" INFO"),
+        Level::Warn => style.set_bold(true).set_color(Color::Yellow).value(" WARN"),
+        Level::Error => style.set_bold(true).set_color(Color::Red).value("ERROR"),
+    }
+}
+
+pub fn init() -> Result<()> {
+    HookBuilder::default()
+        .display_env_section(false)
+        .install()?;
+    init_logger()?;
+    Ok(())
+}
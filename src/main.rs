@@ -1,9 +1,26 @@
+use std::process::ExitCode;
+
 use clap::Parser;
-use color_eyre::eyre::{Context, Result};
+use color_eyre::eyre::Context;
+use shot::ShotError;
+
+use crate::{cli::Opt, logger::init};
 
-mod_use::mod_use![cli, api, model, config, util];
+mod cli;
+mod logger;
+mod progress;
 
-fn main() -> Result<()> {
-    init().wrap_err("Internal error")?;
-    Opt::parse().handle()
+fn main() -> ExitCode {
+    let res = init()
+        .wrap_err("Internal error")
+        .and_then(|_| Opt::parse().handle());
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            e.chain()
+                .find_map(|e| e.downcast_ref::<ShotError>())
+                .map_or(ExitCode::FAILURE, ShotError::exit_code)
+        }
+    }
 }
@@ -0,0 +1,718 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io::{self, Cursor},
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::SystemTime,
+};
+
+use log::{debug, info, warn};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize as De, Serialize as Ser};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Method, Request, Server};
+use url::Url;
+
+use crate::{ApiError, Auth, Image, Response, ShotError, Upload};
+
+type Result<T, E = ShotError> = std::result::Result<T, E>;
+type Reply = tiny_http::Response<Cursor<Vec<u8>>>;
+
+/// Cloudflare Images API served locally, for tests and development without
+/// an account.
+///
+/// Serves upload, list, details, blob, delete, direct upload, stats and
+/// token verify endpoints with the same envelopes and error codes as
+/// Cloudflare, and the `public` variant of images.
+/// Images are kept in memory, or in a directory with [`MockServer::with_dir`]
+/// to survive restarts. Errors can be injected with [`MockServer::fail`].
+///
+/// ```no_run
+/// use shot::{MockServer, API};
+///
+/// # fn main() -> Result<(), shot::ShotError> {
+/// let mock = MockServer::bind("127.0.0.1:0")?;
+/// mock.spawn();
+/// let api = API::new(mock.auth())?.with_client(&mock.client())?;
+/// let image = api.upload("a.png", b"not really a png").send()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct MockServer {
+    server: Arc<Server>,
+    state: Arc<Mutex<State>>,
+}
+
+/// Respond the next `times` requests with `status` instead of handling them.
+///
+/// Parsed from `STATUS` or `STATUSxTIMES`, e.g. `503x2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub status: u16,
+    pub times: u32,
+}
+
+impl FromStr for Fault {
+    type Err = ShotError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad = || ShotError::Config(format!("Bad fault `{s}`, expected e.g. `429` or `503x2`"));
+        let (status, times) = s.split_once('x').unwrap_or((s, "1"));
+        let status = status.parse().map_err(|_| bad())?;
+        if !(400..600).contains(&status) {
+            return Err(bad());
+        }
+        Ok(Fault {
+            status,
+            times: times.parse().map_err(|_| bad())?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct State {
+    token: String,
+    origin: String,
+    dir: Option<PathBuf>,
+    faults: Vec<Fault>,
+    entries: Vec<Entry>,
+}
+
+/// A stored image, or a direct upload not uploaded to yet
+#[derive(Ser, De, Clone)]
+struct Entry {
+    image: Image,
+    draft: bool,
+    content_type: String,
+    #[serde(skip)]
+    blob: Vec<u8>,
+}
+
+impl MockServer {
+    pub const ACCOUNT_ID: &'static str = "mock-account";
+    pub const TOKEN: &'static str = "mock-token";
+    /// Number of images allowed, as reported by stats
+    pub const ALLOWED: u64 = 100_000;
+    const BASE: &'static str = "/client/v4/";
+
+    /// Listen on `addr`, e.g. `127.0.0.1:0` for a random port. Requests are
+    /// handled after [`MockServer::spawn`] or [`MockServer::serve`].
+    pub fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        let server = Server::http(addr).map_err(ShotError::Network)?;
+        let origin = match server.server_addr().to_ip() {
+            Some(addr) => format!("http://{addr}"),
+            None => {
+                return Err(ShotError::Config(
+                    "Mock server must listen on TCP".to_owned(),
+                ))
+            }
+        };
+        Ok(Self {
+            server: Arc::new(server),
+            state: Arc::new(Mutex::new(State {
+                token: Self::TOKEN.to_owned(),
+                origin,
+                ..Default::default()
+            })),
+        })
+    }
+
+    /// Persist images in `dir`, loading those stored by earlier runs
+    pub fn with_dir(self, dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        let index = dir.join("images.json");
+        let entries = match fs::read(&index) {
+            Ok(content) => {
+                let mut entries: Vec<Entry> = serde_json::from_slice(&content).map_err(|e| {
+                    ShotError::Config(format!("Bad mock storage {}: {e}", index.display()))
+                })?;
+                for entry in &mut entries {
+                    let path = blob_path(&dir, &entry.image.id);
+                    entry.blob = fs::read(&path).map_err(|e| io_error(&path, e))?;
+                }
+                entries
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(io_error(&index, e)),
+        };
+        {
+            let mut state = self.state();
+            state.entries = entries;
+            state.dir = Some(dir);
+        }
+        Ok(self)
+    }
+
+    /// Accept `token` instead of [`MockServer::TOKEN`]
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        self.state().token = token.into();
+        self
+    }
+
+    /// Base URL of the API, to be used as `api_base`
+    pub fn url(&self) -> Url {
+        let origin = self.state().origin.clone();
+        Url::parse(&origin).unwrap().join(Self::BASE).unwrap()
+    }
+
+    /// Auth accepted by the server
+    pub fn auth(&self) -> Auth {
+        Auth {
+            account_id: Self::ACCOUNT_ID.to_owned(),
+            token: self.state().token.clone(),
+        }
+    }
+
+    /// HTTP settings sending requests to the server
+    pub fn client(&self) -> crate::Client {
+        crate::Client {
+            api_base: Some(self.url()),
+            ..Default::default()
+        }
+    }
+
+    /// Inject a fault, after those already injected
+    pub fn fail(&self, fault: Fault) {
+        self.state().faults.push(fault)
+    }
+
+    /// Stored images, in upload order
+    pub fn images(&self) -> Vec<Image> {
+        let state = self.state();
+        state
+            .entries
+            .iter()
+            .filter(|x| !x.draft)
+            .map(|x| x.image.clone())
+            .collect()
+    }
+
+    /// Content of image `id` as uploaded
+    pub fn blob(&self, id: &str) -> Option<Vec<u8>> {
+        self.state().find(id).map(|x| x.blob.clone())
+    }
+
+    /// Handle requests in a background thread, until the server is dropped
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let (server, state) = (self.server.clone(), self.state.clone());
+        thread::spawn(move || serve(&server, &state))
+    }
+
+    /// Handle requests in this thread, until the server is dropped
+    pub fn serve(&self) {
+        serve(&self.server, &self.state)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.server.unblock()
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn serve(server: &Server, state: &Mutex<State>) {
+    for mut req in server.incoming_requests() {
+        let reply = lock(state).handle(&mut req);
+        debug!(
+            "Mock: {} {} -> {}",
+            req.method(),
+            req.url(),
+            reply.status_code().0
+        );
+        if let Err(e) = req.respond(reply) {
+            warn!("Mock: failed to respond: {e}")
+        }
+    }
+}
+
+impl State {
+    fn handle(&mut self, req: &mut Request) -> Reply {
+        if let Some(fault) = self.faults.first_mut() {
+            let status = fault.status;
+            fault.times = fault.times.saturating_sub(1);
+            if fault.times == 0 {
+                self.faults.remove(0);
+            }
+            info!("Mock: injected {status} for {} {}", req.method(), req.url());
+            return fault_reply(status);
+        }
+
+        // Urls are relative, so any host works
+        let url = match Url::parse("http://mock").and_then(|x| x.join(req.url())) {
+            Ok(url) => url,
+            Err(_) => return error(400, 7400, "Bad request URL"),
+        };
+        let segments = url
+            .path_segments()
+            .into_iter()
+            .flatten()
+            .map(|x| percent_decode_str(x).decode_utf8_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+        let method = req.method().clone();
+
+        // Direct upload URLs are authorized by their ID
+        if let ["upload", id] = segments[..] {
+            return match method {
+                Method::Post => self.direct_upload(id, req),
+                _ => no_route(),
+            };
+        }
+        // Variants are public, unless signed URLs are required
+        if let ["cdn", account, ref id @ .., variant] = segments[..] {
+            if method != Method::Get || account != MockServer::ACCOUNT_ID || variant != "public" {
+                return text(404, "Not found");
+            }
+            return match self.find(&id.join("/")) {
+                Some(entry) if entry.image.require_signed_urls => text(403, "Forbidden"),
+                Some(entry) => raw(entry),
+                None => text(404, "Not found"),
+            };
+        }
+        let ["client", "v4", ref route @ ..] = segments[..] else {
+            return no_route();
+        };
+        if method == Method::Head {
+            return reply(200, &json!({}));
+        }
+        let bearer = format!("Bearer {}", self.token);
+        if header(req, "Authorization") != Some(bearer.as_str()) {
+            return error(401, 10000, "Authentication error");
+        }
+        let (account, route) = match route {
+            ["user", "tokens", "verify"] => return self.verify(),
+            ["accounts", account, route @ ..] => (*account, route),
+            _ => return no_route(),
+        };
+        if account != MockServer::ACCOUNT_ID {
+            return error(
+                403,
+                7003,
+                &format!(
+                    "Could not route to /accounts/{account}, perhaps your object identifier is \
+                     invalid?"
+                ),
+            );
+        }
+        let query = |key: &str, default: usize| {
+            url.query_pairs()
+                .find(|(k, _)| k == key)
+                .and_then(|(_, v)| v.parse().ok())
+                .unwrap_or(default)
+        };
+        match (method, route) {
+            (Method::Get, ["tokens", "verify"]) => self.verify(),
+            (Method::Get, ["images", "v1"]) => self.list(query("page", 1), query("per_page", 1000)),
+            (Method::Post, ["images", "v1"]) => self.upload(req),
+            (Method::Get, ["images", "v1", "stats"]) => self.stats(),
+            (Method::Get, ["images", "v1", id]) => self.details(id),
+            (Method::Get, ["images", "v1", id, "blob"]) => match self.find(id) {
+                Some(entry) => raw(entry),
+                None => not_found(),
+            },
+            (Method::Delete, ["images", "v1", id]) => self.delete(id),
+            (Method::Post, ["images", "v2", "direct_upload"]) => self.create_direct_upload(),
+            _ => no_route(),
+        }
+    }
+
+    fn verify(&self) -> Reply {
+        ok(json!({
+            "id": "mock-token-id",
+            "status": "active",
+            "expires_on": null,
+            "not_before": null,
+        }))
+    }
+
+    fn list(&self, page: usize, per_page: usize) -> Reply {
+        let per_page = per_page.clamp(10, 10_000);
+        let images = self
+            .entries
+            .iter()
+            .filter(|x| !x.draft)
+            .skip((page.max(1) - 1) * per_page)
+            .take(per_page)
+            .map(|x| &x.image)
+            .collect::<Vec<_>>();
+        ok(json!({ "images": images }))
+    }
+
+    fn stats(&self) -> Reply {
+        let current = self.entries.iter().filter(|x| !x.draft).count();
+        ok(json!({
+            "count": { "allowed": MockServer::ALLOWED, "current": current }
+        }))
+    }
+
+    /// Image `id`, excluding drafts
+    fn find(&self, id: &str) -> Option<&Entry> {
+        self.entries.iter().find(|x| !x.draft && x.image.id == id)
+    }
+
+    fn details(&self, id: &str) -> Reply {
+        match self.find(id) {
+            Some(entry) => ok(&entry.image),
+            None => not_found(),
+        }
+    }
+
+    fn delete(&mut self, id: &str) -> Reply {
+        let Some(i) = self.entries.iter().position(|x| x.image.id == id) else {
+            return not_found();
+        };
+        let entry = self.entries.remove(i);
+        if let Some(ref dir) = self.dir {
+            let _ = fs::remove_file(blob_path(dir, &entry.image.id));
+        }
+        match self.persist() {
+            Ok(_) => ok(json!({})),
+            Err(reply) => reply,
+        }
+    }
+
+    fn create_direct_upload(&mut self) -> Reply {
+        let id = random_id();
+        let entry = Entry {
+            image: self.image(id.clone(), String::new(), false, None),
+            draft: true,
+            content_type: String::new(),
+            blob: vec![],
+        };
+        self.entries.push(entry);
+        if let Err(reply) = self.persist() {
+            return reply;
+        }
+        ok(json!({
+            "id": id,
+            "uploadURL": format!("{}/upload/{id}", self.origin),
+        }))
+    }
+
+    /// Upload to a direct upload URL, which works once
+    fn direct_upload(&mut self, id: &str, req: &mut Request) -> Reply {
+        let Some(i) = self
+            .entries
+            .iter()
+            .position(|x| x.draft && x.image.id == id)
+        else {
+            return error(404, 5404, "Direct upload not found or already used");
+        };
+        let form = match read_form(req) {
+            Ok(form) => form,
+            Err(reply) => return reply,
+        };
+        self.entries.remove(i);
+        self.store(Some(id.to_owned()), form)
+    }
+
+    fn upload(&mut self, req: &mut Request) -> Reply {
+        let form = match read_form(req) {
+            Ok(form) => form,
+            Err(reply) => return reply,
+        };
+        let text = |name| form.iter().find(|x| x.name == name).map(Part::text);
+        let id = text("id");
+        if let Some(ref id) = id {
+            if text("requireSignedURLs").is_some_and(|x| x == "true") {
+                return error(
+                    400,
+                    5400,
+                    "Error parsing form fields: custom ID can't be used with requireSignedURLs",
+                );
+            }
+            if self.entries.iter().any(|x| &x.image.id == id) {
+                return error(409, 5409, "Resource already exists");
+            }
+        }
+        self.store(id, form)
+    }
+
+    /// Validate fields of an upload form like Cloudflare, and store the image
+    fn store(&mut self, id: Option<String>, form: Vec<Part>) -> Reply {
+        let Some(file) = form
+            .iter()
+            .find(|x| x.name == "file" && x.filename.is_some())
+        else {
+            return error(400, 5400, "Error parsing form fields: missing file");
+        };
+        if file.content.len() > Upload::IMAGE_LIMIT {
+            return fault_reply(413);
+        }
+        let text = |name| form.iter().find(|x| x.name == name).map(Part::text);
+        let meta = match text("metadata") {
+            None => Map::new(),
+            Some(meta) if meta.len() > Upload::META_LIMIT => {
+                return error(400, 5411, "Metadata must not be larger than 1024 bytes")
+            }
+            Some(meta) => match serde_json::from_str(&meta) {
+                Ok(meta) => meta,
+                Err(_) => return error(400, 5400, "Error parsing form fields: bad metadata"),
+            },
+        };
+        let signed = text("requireSignedURLs").is_some_and(|x| x == "true");
+
+        let id = id.unwrap_or_else(random_id);
+        let filename = file.filename.clone().unwrap_or_default();
+        let entry = Entry {
+            image: self.image(id, filename, signed, Some(meta)),
+            draft: false,
+            content_type: file.content_type.clone(),
+            blob: file.content.clone(),
+        };
+        if let Some(ref dir) = self.dir {
+            let path = blob_path(dir, &entry.image.id);
+            let written = path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&path, &entry.blob));
+            if let Err(e) = written {
+                return internal(&io_error(&path, e));
+            }
+        }
+        let image = entry.image.clone();
+        self.entries.push(entry);
+        match self.persist() {
+            Ok(_) => ok(image),
+            Err(reply) => reply,
+        }
+    }
+
+    fn image(
+        &self,
+        id: String,
+        filename: String,
+        signed: bool,
+        meta: Option<Map<String, Value>>,
+    ) -> Image {
+        let mut variant = Url::parse(&self.origin).unwrap();
+        variant
+            .path_segments_mut()
+            .unwrap()
+            .extend(["cdn", MockServer::ACCOUNT_ID])
+            .extend(id.split('/'))
+            .push("public");
+        Image {
+            id,
+            filename,
+            require_signed_urls: signed,
+            uploaded: SystemTime::now(),
+            variants: vec![variant],
+            meta: meta.map(|x| x.into_iter().collect()),
+            deletion_url: None,
+        }
+    }
+
+    /// Write the index, if persisted in a directory
+    fn persist(&self) -> Result<(), Reply> {
+        let Some(ref dir) = self.dir else {
+            return Ok(());
+        };
+        let index = dir.join("images.json");
+        // Entries are always serializable
+        let content = serde_json::to_vec_pretty(&self.entries).unwrap();
+        fs::create_dir_all(dir)
+            .and_then(|_| fs::write(&index, content))
+            .map_err(|e| internal(&io_error(&index, e)))
+    }
+}
+
+/// A multipart form field
+struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: String,
+    content: Vec<u8>,
+}
+
+impl Part {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.content).into_owned()
+    }
+}
+
+fn read_form(req: &mut Request) -> Result<Vec<Part>, Reply> {
+    let bad = |reason: &str| error(400, 5400, &format!("Error parsing form fields: {reason}"));
+    let boundary = header(req, "Content-Type")
+        .filter(|x| x.starts_with("multipart/form-data"))
+        .and_then(|x| x.split_once("boundary="))
+        .map(|(_, b)| b.trim_matches('"').to_owned())
+        .ok_or_else(|| bad("not a multipart form"))?;
+    let mut body = vec![];
+    req.as_reader()
+        .read_to_end(&mut body)
+        .map_err(|_| bad("incomplete body"))?;
+    parse_form(&body, &boundary).ok_or_else(|| bad("malformed form"))
+}
+
+fn parse_form(body: &[u8], boundary: &str) -> Option<Vec<Part>> {
+    let delimiter = format!("--{boundary}");
+    let next = format!("\r\n{delimiter}");
+    let mut parts = vec![];
+    let mut rest = &body[find(body, delimiter.as_bytes())? + delimiter.len()..];
+    loop {
+        if rest.starts_with(b"--") {
+            return Some(parts);
+        }
+        rest = rest.strip_prefix(b"\r\n")?;
+        let end = find(rest, next.as_bytes())?;
+        let part = &rest[..end];
+        rest = &rest[end + next.len()..];
+
+        let split = find(part, b"\r\n\r\n")?;
+        let headers = std::str::from_utf8(&part[..split]).ok()?;
+        let param = |key: &str| {
+            let pattern = format!("; {key}=\"");
+            let start = headers.find(&pattern)? + pattern.len();
+            let len = headers[start..].find('"')?;
+            Some(headers[start..start + len].to_owned())
+        };
+        let content_type = headers
+            .lines()
+            .find_map(|x| {
+                let (k, v) = x.split_once(':')?;
+                k.eq_ignore_ascii_case("content-type")
+                    .then(|| v.trim().to_owned())
+            })
+            .unwrap_or_else(|| "text/plain".to_owned());
+        parts.push(Part {
+            name: param("name")?,
+            filename: param("filename"),
+            content_type,
+            content: part[split + 4..].to_vec(),
+        });
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|x| x == needle)
+}
+
+fn header<'a>(req: &'a Request, name: &'static str) -> Option<&'a str> {
+    req.headers()
+        .iter()
+        .find(|x| x.field.equiv(name))
+        .map(|x| x.value.as_str())
+}
+
+fn reply(status: u16, body: &impl Ser) -> Reply {
+    // Values built here are always serializable
+    let body = serde_json::to_vec(body).unwrap();
+    tiny_http::Response::from_data(body)
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+/// Image content, as served by the blob endpoint and variants
+fn raw(entry: &Entry) -> Reply {
+    let content_type = Header::from_bytes("Content-Type", entry.content_type.as_bytes())
+        .unwrap_or_else(|_| Header::from_bytes("Content-Type", "image/png").unwrap());
+    tiny_http::Response::from_data(entry.blob.clone()).with_header(content_type)
+}
+
+/// Plain text response, like errors of image delivery
+fn text(status: u16, body: &str) -> Reply {
+    tiny_http::Response::from_string(body).with_status_code(status)
+}
+
+fn ok(result: impl Ser) -> Reply {
+    reply(
+        200,
+        &Response {
+            success: true,
+            result: Some(result),
+            result_info: None,
+            messages: Some(vec![]),
+            errors: vec![],
+            status: None,
+        },
+    )
+}
+
+fn error(status: u16, code: u32, message: &str) -> Reply {
+    reply(
+        status,
+        &Response::<()> {
+            success: false,
+            result: None,
+            result_info: None,
+            messages: Some(vec![]),
+            errors: vec![ApiError {
+                code,
+                message: message.to_owned(),
+            }],
+            status: None,
+        },
+    )
+}
+
+fn not_found() -> Reply {
+    error(404, 5404, "Image not found")
+}
+
+fn no_route() -> Reply {
+    error(404, 7000, "No route for that URI")
+}
+
+fn internal(e: &ShotError) -> Reply {
+    warn!("Mock: {e}");
+    error(500, 10001, "Internal server error")
+}
+
+/// Response of injected `status`, with the body Cloudflare sends
+fn fault_reply(status: u16) -> Reply {
+    match status {
+        401 => error(401, 10000, "Authentication error"),
+        403 => error(403, 10000, "Authentication error"),
+        413 => error(413, 5412, "Image must be smaller than 10 megabytes"),
+        429 => error(
+            429,
+            971,
+            "Please wait and consider throttling your request speed",
+        )
+        .with_header(Header::from_bytes("Retry-After", "1").unwrap()),
+        500.. => error(status, 10001, "Internal server error"),
+        _ => error(status, 5400, "Bad request"),
+    }
+}
+
+/// Random ID in the format of Cloudflare image IDs, a UUID
+fn random_id() -> String {
+    let random = || RandomState::new().build_hasher().finish();
+    let hex = format!("{:016x}{:016x}", random(), random());
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Path of content of image `id`, hashed as IDs may contain `/`
+fn blob_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join("blobs")
+        .join(format!("{:x}", Sha256::digest(id.as_bytes())))
+}
+
+fn io_error(path: &Path, source: io::Error) -> ShotError {
+    ShotError::Io {
+        path: path.to_owned(),
+        source,
+    }
+}
@@ -1,15 +1,91 @@
 use std::{collections::HashMap, time::SystemTime};
 
-use serde::{Deserialize as De, Serialize as Se};
+use log::{debug, info};
+use serde::{de::DeserializeOwned, Deserialize as De, Serialize as Se};
+use serde_json::Value;
 use url::Url;
 
+use crate::ShotError;
+
 #[derive(Se, De, Debug, Clone)]
 pub struct Response<T> {
     pub success: bool,
     pub result: Option<T>,
-    pub result_info: Option<String>,
-    pub messages: Option<Vec<String>>,
+    pub result_info: Option<Value>,
+    pub messages: Option<Vec<Message>>,
     pub errors: Vec<ApiError>,
+    /// HTTP status this response came with, if known
+    #[serde(skip)]
+    pub status: Option<u16>,
+}
+
+impl<T: DeserializeOwned> Response<T> {
+    /// Parse body of a response even if it has an error status, since
+    /// Cloudflare reports errors in the body
+    pub fn parse(status: u16, text: String) -> Result<Self, ShotError> {
+        debug!("Res ({status}): {text}");
+        let is_success = (200..300).contains(&status);
+        match serde_json::from_str::<Response<T>>(&text) {
+            Ok(res) => Ok(Response {
+                status: Some(status),
+                ..res
+            }),
+            Err(_) if !is_success => Err(ShotError::Status { status, body: text }),
+            Err(source) => Err(ShotError::InvalidResponse {
+                status,
+                body: text,
+                source,
+            }),
+        }
+    }
+}
+
+impl<T> Response<T> {
+    /// `result` if the request succeeded, or errors in the body otherwise.
+    /// `success: false` is an error even with HTTP 200.
+    pub fn into_result(self) -> Result<T, ShotError> {
+        let status = self.status;
+        self.into_option()?
+            .ok_or_else(|| ShotError::InvalidResponse {
+                status: status.unwrap_or(200),
+                body: String::new(),
+                source: serde::de::Error::missing_field("result"),
+            })
+    }
+
+    /// Like [`Response::into_result`], for endpoints that may return no
+    /// `result`, e.g. delete
+    pub fn into_option(self) -> Result<Option<T>, ShotError> {
+        if let Some(ref msgs) = self.messages {
+            msgs.iter().for_each(|msg| info!("Message from API: {msg}"))
+        }
+        if self.success {
+            Ok(self.result)
+        } else {
+            Err(ShotError::Api {
+                status: self.status,
+                errors: self.errors,
+            })
+        }
+    }
+}
+
+/// Some endpoints return plain text messages, others return code-message
+/// pairs
+#[derive(Se, De, Debug, Clone)]
+#[serde(untagged)]
+pub enum Message {
+    Text(String),
+    Detailed(ApiError),
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Message::Text(text) => f.write_str(text),
+            Message::Detailed(err) => f.write_str(&err.message),
+        }
+    }
 }
 
 #[derive(Se, De, Debug, Clone)]
@@ -21,11 +97,97 @@ pub struct Image {
     #[serde(with = "humantime_serde")]
     pub uploaded: SystemTime,
     pub variants: Vec<Url>,
-    pub meta: Option<HashMap<String, String>>,
+    pub meta: Option<HashMap<String, Value>>,
+    /// URL that deletes the image when visited, given by some custom
+    /// uploaders
+    #[serde(
+        default,
+        rename = "deletionURL",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub deletion_url: Option<Url>,
+}
+
+/// A page of images
+#[derive(Se, De, Debug, Clone)]
+pub struct ImageList {
+    pub images: Vec<Image>,
 }
 
 #[derive(Se, De, Debug, Clone)]
 pub struct ApiError {
-    pub code: u16,
+    pub code: u32,
     pub message: String,
 }
+
+impl ApiError {
+    /// What to do about this error, for codes commonly seen by users
+    pub fn hint(&self) -> Option<&'static str> {
+        Some(match self.code {
+            1000 => "The token is invalid. Check for typos, or whether it has been revoked",
+            6003 | 6111 => {
+                "The token is malformed. Make sure it's copied without surrounding whitespace"
+            }
+            7003 | 7000 => {
+                "The account ID is malformed. It can be found in the URL of the dashboard"
+            }
+            9109 => "The token is invalid or expired. Create a new one in the dashboard",
+            10000 => {
+                "The token is not allowed to do this. Check the account ID, and make sure the \
+                 token has `Account.Cloudflare Images` permission"
+            }
+            5403 => "Cloudflare Images is not enabled for this account, or the account ID is wrong",
+            5409 => "An image with this custom ID already exists",
+            5453..=5455 => "Image format is not supported by Cloudflare Images",
+            5412 => "Image is too large. The limit is 10 MB",
+            _ => return None,
+        })
+    }
+}
+
+/// Result of token verification
+#[derive(Se, De, Debug, Clone)]
+pub struct TokenStatus {
+    pub id: String,
+    /// `active`, `disabled` or `expired`
+    pub status: String,
+    pub expires_on: Option<String>,
+    pub not_before: Option<String>,
+}
+
+/// Image count of the account
+#[derive(Se, De, Debug, Clone)]
+pub struct ImageStats {
+    pub count: ImageCount,
+}
+
+#[derive(Se, De, Debug, Clone, Copy)]
+pub struct ImageCount {
+    pub allowed: u64,
+    pub current: u64,
+}
+
+/// One-time upload URL, see
+/// <https://developers.cloudflare.com/images/cloudflare-images/upload-images/direct-creator-upload/>
+#[derive(Se, De, Debug, Clone)]
+pub struct DirectUpload {
+    pub id: String,
+    #[serde(rename = "uploadURL")]
+    pub upload_url: Url,
+}
+
+impl Image {
+    /// Name of variant from its url, which is the last path segment
+    pub fn variant_name(url: &Url) -> &str {
+        url.path_segments()
+            .and_then(|mut x| x.next_back())
+            .unwrap_or("UNKNOWN")
+    }
+
+    /// Url of variant `name`
+    pub fn variant(&self, name: &str) -> Option<&Url> {
+        self.variants
+            .iter()
+            .find(|url| Self::variant_name(url) == name)
+    }
+}
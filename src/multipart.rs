@@ -0,0 +1,248 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Debug,
+    hash::{BuildHasher, Hasher},
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::Mutex,
+};
+
+use attohttpc::body::{Body, BodyKind};
+use sha2::{Digest, Sha256};
+
+/// Readers that uploads can be streamed from
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Image content of an upload, either in memory or streamed from a reader
+pub enum Content<'a> {
+    Bytes(&'a [u8]),
+    /// Read from the start every time it's sent, so uploads can be retried
+    Reader {
+        reader: Mutex<Box<dyn ReadSeek>>,
+        len: u64,
+    },
+}
+
+impl<'a> Content<'a> {
+    /// Stream `len` bytes from `reader`, e.g. a file
+    pub fn reader(reader: impl ReadSeek + 'static, len: u64) -> Self {
+        Content::Reader {
+            reader: Mutex::new(Box::new(reader)),
+            len,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            Content::Bytes(bytes) => bytes.len() as u64,
+            Content::Reader { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write all content into `w`, from the start
+    pub fn write_to(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Content::Bytes(bytes) => w.write_all(bytes),
+            Content::Reader { reader, len } => {
+                let mut reader = reader.lock().unwrap_or_else(|e| e.into_inner());
+                reader.seek(SeekFrom::Start(0))?;
+                let copied = io::copy(&mut reader.as_mut().take(*len), w)?;
+                if copied != *len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("Expected {len} bytes to upload, read {copied}"),
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Hex encoded SHA-256, streamed for readers
+    pub fn sha256(&self) -> io::Result<String> {
+        let mut hasher = Sha256::new();
+        self.write_to(&mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+impl Debug for Content<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Content::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Content::Reader { len, .. } => write!(f, "Reader({len} bytes)"),
+        }
+    }
+}
+
+/// A multipart form field, turned into a form by each client
+pub(crate) enum Field<'a> {
+    Text(&'a str, String),
+    File {
+        name: &'a str,
+        filename: &'a str,
+        content_type: &'a str,
+        content: &'a Content<'a>,
+    },
+}
+
+impl Field<'_> {
+    pub fn direct_upload() -> Vec<Self> {
+        vec![Field::Text("requireSignedURLs", "false".to_owned())]
+    }
+}
+
+/// Called with bytes sent and total bytes while a form is being sent
+pub type Progress<'a> = &'a (dyn Fn(u64, u64) + Sync);
+
+/// Multipart form with a known length. Files are streamed into the request
+/// instead of being copied into the body first.
+pub(crate) struct Form<'a> {
+    boundary: String,
+    fields: &'a [Field<'a>],
+    progress: Option<Progress<'a>>,
+}
+
+impl<'a> Form<'a> {
+    pub fn new(fields: &'a [Field<'a>], progress: Option<Progress<'a>>) -> Self {
+        let random = || RandomState::new().build_hasher().finish();
+        Self {
+            boundary: format!("shot-{:016x}{:016x}", random(), random()),
+            fields,
+            progress,
+        }
+    }
+
+    /// Part header of `field`, including the boundary line
+    fn header(&self, field: &Field) -> String {
+        match field {
+            Field::Text(name, _) => format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                self.boundary,
+                escape(name)
+            ),
+            Field::File {
+                name,
+                filename,
+                content_type,
+                ..
+            } => format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"; \
+                 filename=\"{}\"\r\nContent-Type: {content_type}\r\n\r\n",
+                self.boundary,
+                escape(name),
+                escape(filename)
+            ),
+        }
+    }
+
+    fn footer(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+
+    fn len(&self) -> u64 {
+        let parts: u64 = self
+            .fields
+            .iter()
+            .map(|field| {
+                let content = match field {
+                    Field::Text(_, text) => text.len() as u64,
+                    Field::File { content, .. } => content.len(),
+                };
+                self.header(field).len() as u64 + content + 2
+            })
+            .sum();
+        parts + self.footer().len() as u64
+    }
+}
+
+impl Body for Form<'_> {
+    fn kind(&mut self) -> io::Result<BodyKind> {
+        Ok(BodyKind::KnownLength(self.len()))
+    }
+
+    fn write<W: Write>(&mut self, writer: W) -> io::Result<()> {
+        let mut writer = Counting {
+            inner: writer,
+            sent: 0,
+            total: self.len(),
+            progress: self.progress,
+        };
+        for field in self.fields {
+            writer.write_all(self.header(field).as_bytes())?;
+            match field {
+                Field::Text(_, text) => writer.write_all(text.as_bytes())?,
+                Field::File { content, .. } => content.write_to(&mut writer)?,
+            }
+            writer.write_all(b"\r\n")?;
+        }
+        writer.write_all(self.footer().as_bytes())?;
+        writer.flush()
+    }
+
+    fn content_type(&mut self) -> io::Result<Option<String>> {
+        Ok(Some(format!(
+            "multipart/form-data; boundary={}",
+            self.boundary
+        )))
+    }
+}
+
+/// Content sent as the whole body, e.g. of an S3 `PUT`
+pub(crate) struct Raw<'a> {
+    pub content: &'a Content<'a>,
+    pub progress: Option<Progress<'a>>,
+}
+
+impl Body for Raw<'_> {
+    fn kind(&mut self) -> io::Result<BodyKind> {
+        Ok(BodyKind::KnownLength(self.content.len()))
+    }
+
+    fn write<W: Write>(&mut self, writer: W) -> io::Result<()> {
+        let mut writer = Counting {
+            inner: writer,
+            sent: 0,
+            total: self.content.len(),
+            progress: self.progress,
+        };
+        self.content.write_to(&mut writer)?;
+        writer.flush()
+    }
+}
+
+/// Quotes and line breaks would end the header early
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Reports bytes written to `progress`
+struct Counting<'a, W> {
+    inner: W,
+    sent: u64,
+    total: u64,
+    progress: Option<Progress<'a>>,
+}
+
+impl<W: Write> Write for Counting<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.sent += written as u64;
+        if let Some(progress) = self.progress {
+            progress(self.sent, self.total)
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+use color_eyre::Result;
+use image::{
+    codecs::png::{self as png_codec, PngEncoder},
+    DynamicImage, GenericImageView, ImageEncoder,
+};
+use log::debug;
+use png::{AdaptiveFilterType, BitDepth, ColorType, Compression, Encoder, FilterType};
+use serde::{Deserialize as De, Serialize as Ser};
+
+use crate::ShotError;
+
+/// How hard the PNG optimizer tries. Higher levels try more filters and are
+/// slower, but the result is always lossless.
+#[derive(Ser, De, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OptimizeLevel {
+    /// Fast compression, adaptive filter only
+    Fast,
+    /// Best compression, no filter and adaptive filter
+    #[default]
+    Default,
+    /// Best compression, every filter
+    Max,
+}
+
+impl OptimizeLevel {
+    fn compression(self) -> Compression {
+        match self {
+            OptimizeLevel::Fast => Compression::Fast,
+            _ => Compression::Best,
+        }
+    }
+
+    /// Filters to be tried. `None` stands for adaptive filtering.
+    fn filters(self) -> &'static [Option<FilterType>] {
+        match self {
+            OptimizeLevel::Fast => &[None],
+            OptimizeLevel::Default => &[Some(FilterType::NoFilter), None],
+            OptimizeLevel::Max => &[
+                Some(FilterType::NoFilter),
+                Some(FilterType::Sub),
+                Some(FilterType::Up),
+                Some(FilterType::Avg),
+                Some(FilterType::Paeth),
+                None,
+            ],
+        }
+    }
+}
+
+/// Losslessly encode `img` into PNG, trying palette and bit depth reduction
+/// and a set of filters, and keep the smallest output.
+pub fn optimize_png(img: &DynamicImage, level: OptimizeLevel) -> Result<Vec<u8>> {
+    let img = match reduce_depth(img) {
+        Some(img) => img,
+        // 16-bit samples that cannot be reduced are kept as is, only
+        // compression is tuned
+        None => return encode_wide(img),
+    };
+
+    let candidates = Raw::candidates(&img);
+    let mut best: Option<Vec<u8>> = None;
+
+    for raw in &candidates {
+        for &filter in level.filters() {
+            let buf = raw.encode(level.compression(), filter)?;
+            debug!(
+                "PNG candidate {:?} {:?}, filter {:?}: {} bytes",
+                raw.color,
+                raw.depth,
+                filter,
+                buf.len()
+            );
+            if best.as_ref().map_or(true, |b| buf.len() < b.len()) {
+                best = Some(buf);
+            }
+        }
+    }
+
+    Ok(best.expect("There's always at least one candidate"))
+}
+
+/// Convert `img` to 8-bit if it is 8-bit already, or if every 16-bit sample
+/// is representable in 8 bits
+fn reduce_depth(img: &DynamicImage) -> Option<DynamicImage> {
+    let wide = match img {
+        DynamicImage::ImageLuma16(img) => img.as_raw(),
+        DynamicImage::ImageLumaA16(img) => img.as_raw(),
+        DynamicImage::ImageRgb16(img) => img.as_raw(),
+        DynamicImage::ImageRgba16(img) => img.as_raw(),
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => return None,
+        img => return Some(img.clone()),
+    };
+    wide.iter()
+        .all(|v| v >> 8 == v & 0xff)
+        .then(|| DynamicImage::ImageRgba8(img.to_rgba8()))
+}
+
+fn encode_wide(img: &DynamicImage) -> Result<Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let mut buf = Vec::with_capacity(img.as_bytes().len());
+    PngEncoder::new_with_quality(
+        &mut buf,
+        png_codec::CompressionType::Best,
+        png_codec::FilterType::Adaptive,
+    )
+    .write_image(img.as_bytes(), width, height, img.color())
+    .map_err(|e| ShotError::Encode(e.into()))?;
+    Ok(buf)
+}
+
+/// Pixel data ready to be handed to the PNG encoder
+struct Raw {
+    width: u32,
+    height: u32,
+    color: ColorType,
+    depth: BitDepth,
+    palette: Option<(Vec<u8>, Vec<u8>)>,
+    data: Vec<u8>,
+}
+
+impl Raw {
+    /// All lossless representations worth trying for an 8-bit image
+    fn candidates(img: &DynamicImage) -> Vec<Raw> {
+        let (width, height) = img.dimensions();
+        let rgba = img.to_rgba8();
+        let pixels = rgba.pixels().map(|px| px.0).collect::<Vec<_>>();
+
+        let opaque = pixels.iter().all(|px| px[3] == 255);
+        let gray = pixels.iter().all(|px| px[0] == px[1] && px[1] == px[2]);
+
+        let mut candidates = vec![];
+
+        let (color, samples): (_, Vec<u8>) = match (gray, opaque) {
+            (true, true) => (
+                ColorType::Grayscale,
+                pixels.iter().map(|px| px[0]).collect(),
+            ),
+            (true, false) => (
+                ColorType::GrayscaleAlpha,
+                pixels.iter().flat_map(|px| [px[0], px[3]]).collect(),
+            ),
+            (false, true) => (
+                ColorType::Rgb,
+                pixels.iter().flat_map(|px| [px[0], px[1], px[2]]).collect(),
+            ),
+            (false, false) => (ColorType::Rgba, rgba.into_raw()),
+        };
+
+        if color == ColorType::Grayscale {
+            // Gray levels evenly spaced on a lower bit depth, e.g. pure black
+            // and white, can be stored with fewer bits
+            let (depth, step) = [
+                (BitDepth::One, 255),
+                (BitDepth::Two, 85),
+                (BitDepth::Four, 17),
+            ]
+            .into_iter()
+            .find(|(_, step)| samples.iter().all(|v| v % step == 0))
+            .unwrap_or((BitDepth::Eight, 1));
+            let values = samples.iter().map(|v| v / step).collect::<Vec<_>>();
+            candidates.push(Raw {
+                width,
+                height,
+                color,
+                depth,
+                palette: None,
+                data: pack(&values, width, depth),
+            });
+        } else {
+            candidates.push(Raw {
+                width,
+                height,
+                color,
+                depth: BitDepth::Eight,
+                palette: None,
+                data: samples,
+            });
+        }
+
+        if let Some(raw) = Self::indexed(&pixels, width, height) {
+            candidates.push(raw)
+        }
+
+        candidates
+    }
+
+    /// Palette representation, if the image has no more than 256 colors
+    fn indexed(pixels: &[[u8; 4]], width: u32, height: u32) -> Option<Raw> {
+        let mut colors = Vec::<[u8; 4]>::new();
+        let mut seen = HashSet::<[u8; 4]>::new();
+        for px in pixels {
+            if seen.insert(*px) {
+                if colors.len() == 256 {
+                    return None;
+                }
+                colors.push(*px);
+            }
+        }
+
+        // Translucent entries go first so `tRNS` can be truncated after the
+        // last one
+        colors.sort_by_key(|px| px[3] == 255);
+        let index = colors
+            .iter()
+            .enumerate()
+            .map(|(i, px)| (*px, i as u8))
+            .collect::<HashMap<_, _>>();
+
+        let depth = match colors.len() {
+            0..=2 => BitDepth::One,
+            3..=4 => BitDepth::Two,
+            5..=16 => BitDepth::Four,
+            _ => BitDepth::Eight,
+        };
+        let plte = colors.iter().flat_map(|px| [px[0], px[1], px[2]]).collect();
+        let trns = colors
+            .iter()
+            .map(|px| px[3])
+            .take_while(|a| *a != 255)
+            .collect();
+        let values = pixels.iter().map(|px| index[px]).collect::<Vec<_>>();
+
+        Some(Raw {
+            width,
+            height,
+            color: ColorType::Indexed,
+            depth,
+            palette: Some((plte, trns)),
+            data: pack(&values, width, depth),
+        })
+    }
+
+    fn encode(&self, compression: Compression, filter: Option<FilterType>) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(self.data.len() / 2);
+        let mut encoder = Encoder::new(&mut buf, self.width, self.height);
+        encoder.set_color(self.color);
+        encoder.set_depth(self.depth);
+        encoder.set_compression(compression);
+        match filter {
+            Some(filter) => encoder.set_filter(filter),
+            None => encoder.set_adaptive_filter(AdaptiveFilterType::Adaptive),
+        }
+        if let Some((ref plte, ref trns)) = self.palette {
+            encoder.set_palette(plte.as_slice());
+            if !trns.is_empty() {
+                encoder.set_trns(trns.as_slice());
+            }
+        }
+
+        let encode_err = |e: png::EncodingError| ShotError::Encode(e.into());
+        let mut writer = encoder.write_header().map_err(encode_err)?;
+        writer.write_image_data(&self.data).map_err(encode_err)?;
+        writer.finish().map_err(encode_err)?;
+
+        Ok(buf)
+    }
+}
+
+/// Pack one-sample-per-byte `values` into rows of `depth` bits per sample.
+/// Each row starts on a byte boundary as PNG requires.
+fn pack(values: &[u8], width: u32, depth: BitDepth) -> Vec<u8> {
+    let bits = depth as usize;
+    if bits == 8 {
+        return values.to_vec();
+    }
+
+    let per_byte = 8 / bits;
+    values
+        .chunks(width as usize)
+        .flat_map(|row| {
+            row.chunks(per_byte).map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, v)| byte | v << (8 - bits * (i + 1)))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, ImageBuffer, Luma, Rgba, RgbaImage};
+
+    use super::*;
+
+    /// Optimize `img` at every level and check it decodes to the same pixels
+    fn assert_lossless(img: DynamicImage) {
+        for level in [
+            OptimizeLevel::Fast,
+            OptimizeLevel::Default,
+            OptimizeLevel::Max,
+        ] {
+            let png = optimize_png(&img, level).unwrap();
+            let decoded = image::load_from_memory(&png).unwrap();
+            assert_eq!(decoded.dimensions(), img.dimensions(), "{level:?}");
+            assert!(decoded.to_rgba8() == img.to_rgba8(), "{level:?}");
+        }
+    }
+
+    #[test]
+    fn rgba_round_trip() {
+        // Few colors with alpha, reduced to a palette with transparency
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 128], [0, 0, 255, 0]];
+        assert_lossless(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+            13,
+            7,
+            |x, y| Rgba(colors[(x * y + x) as usize % colors.len()]),
+        )));
+        // Too many colors for a palette
+        assert_lossless(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+            40,
+            30,
+            |x, y| Rgba([x as u8 * 6, y as u8 * 8, (x * y) as u8, 255 - x as u8]),
+        )));
+    }
+
+    #[test]
+    fn grey_round_trip() {
+        // Two and four levels, reduced to 1 and 2 bits per pixel, with widths
+        // not a multiple of the pixels per byte
+        for levels in [2, 4] {
+            assert_lossless(DynamicImage::ImageLuma8(GrayImage::from_fn(
+                11,
+                5,
+                |x, y| Luma([((x + y) % levels * (255 / (levels - 1))) as u8]),
+            )));
+        }
+        assert_lossless(DynamicImage::ImageLuma8(GrayImage::from_fn(
+            17,
+            16,
+            |x, y| Luma([(x * 16 + y) as u8]),
+        )));
+        // 16-bit samples representable in 8 bits, and ones that aren't
+        for scale in [257, 1] {
+            let img: ImageBuffer<Luma<u16>, _> =
+                ImageBuffer::from_fn(9, 9, |x, y| Luma([((x * 9 + y) * scale) as u16]));
+            let png = optimize_png(&DynamicImage::ImageLuma16(img.clone()), Default::default());
+            let decoded = image::load_from_memory(&png.unwrap()).unwrap();
+            assert!(decoded.to_luma16() == img, "{scale}");
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use color_eyre::{eyre::Context, Result};
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use serde::{Deserialize as De, Serialize as Ser};
+
+/// Low-quality placeholder of an image, for frontends to show before the
+/// image itself is loaded
+#[derive(Ser, De, Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    /// BlurHash string, see <https://blurha.sh>
+    pub blurhash: String,
+    /// Most common color, in format of `#rrggbb`
+    pub dominant_color: String,
+    /// Width divided by height
+    pub aspect_ratio: f64,
+}
+
+impl Placeholder {
+    /// Keys used when stored as image metadata
+    pub const BLURHASH: &'static str = "blurhash";
+    pub const DOMINANT_COLOR: &'static str = "dominant_color";
+    pub const ASPECT_RATIO: &'static str = "aspect_ratio";
+
+    pub fn from_image(img: &DynamicImage) -> Result<Self> {
+        let (width, height) = img.dimensions();
+
+        // Neither of these needs full resolution, a thumbnail is way faster
+        let thumb = img.resize(64, 64, FilterType::Triangle).into_rgba8();
+        let (tw, th) = thumb.dimensions();
+
+        let (cx, cy) = if width >= height { (4, 3) } else { (3, 4) };
+        let blurhash = blurhash::encode(cx, cy, tw, th, thumb.as_raw())
+            .wrap_err("Failed to compute BlurHash")?;
+
+        // Bucket colors by their 4 most significant bits per channel, then
+        // average the most common bucket
+        let mut buckets = HashMap::<[u8; 3], (u32, [u32; 3])>::new();
+        thumb.pixels().filter(|px| px[3] > 127).for_each(|px| {
+            let (count, sum) = buckets
+                .entry([px[0] >> 4, px[1] >> 4, px[2] >> 4])
+                .or_default();
+            *count += 1;
+            (0..3).for_each(|i| sum[i] += px[i] as u32);
+        });
+        let dominant_color = buckets
+            .values()
+            .max_by_key(|(count, _)| *count)
+            .map(|(count, sum)| sum.map(|s| (s / count) as u8))
+            .map(|[r, g, b]| format!("#{r:02x}{g:02x}{b:02x}"))
+            .unwrap_or_else(|| "#000000".to_owned());
+
+        Ok(Self {
+            blurhash,
+            dominant_color,
+            aspect_ratio: width as f64 / height.max(1) as f64,
+        })
+    }
+
+    /// Key-value pairs to be stored as image metadata
+    pub fn meta(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (Self::BLURHASH, self.blurhash.clone()),
+            (Self::DOMINANT_COLOR, self.dominant_color.clone()),
+            (Self::ASPECT_RATIO, format!("{:.4}", self.aspect_ratio)),
+        ]
+    }
+}
@@ -0,0 +1,427 @@
+use std::{convert::Infallible, fs, path::PathBuf, str::FromStr};
+
+use clap::{Parser, ValueEnum};
+use color_eyre::{
+    eyre::{eyre, Context},
+    Result,
+};
+use embedded_graphics::{
+    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, OriginDimensions, Pixel, Point, Size},
+    text::{Baseline, Text},
+    Drawable,
+};
+use image::{
+    imageops::{self, FilterType},
+    io::Reader,
+    DynamicImage, GenericImage, GenericImageView, GrayImage, Luma, Rgba, RgbaImage,
+};
+use log::{debug, warn};
+use serde::{Deserialize as De, Serialize as Ser};
+
+use crate::{encode_png, optimize_png, OptimizeLevel, Placeholder, ShotError};
+
+/// Image processing flags shared by `paste` and `upload`. Applied to the
+/// decoded image before encoding.
+///
+/// Also used in [`Config`](crate::Config) as defaults, where flags given on
+/// the command line take precedence.
+#[derive(Parser, Ser, De, Debug, Clone, Default)]
+#[group(skip)]
+#[serde(default)]
+pub struct Process {
+    #[clap(long = "redact", value_name = "X,Y,W,H")]
+    /// Region to be redacted before upload, in pixels. Takes multiple values
+    pub redact: Vec<Region>,
+
+    #[clap(long, value_enum)]
+    /// How redacted regions are obscured [default: blur]
+    pub redact_style: Option<RedactStyle>,
+
+    #[clap(long, value_name = "FILE")]
+    /// RON file with saved redact regions, for fixed-layout screenshots
+    pub redact_preset: Option<PathBuf>,
+
+    #[clap(long, value_name = "IMAGE")]
+    /// Image to be drawn on top of the uploaded image
+    pub watermark: Option<PathBuf>,
+
+    #[clap(long, value_enum)]
+    /// Where the watermark is placed [default: bottom-right]
+    pub watermark_position: Option<Position>,
+
+    #[clap(long, value_name = "0.0-1.0")]
+    /// Opacity of the watermark [default: 0.5]
+    pub watermark_opacity: Option<f32>,
+
+    #[clap(long, value_name = "RATIO")]
+    /// Width of the watermark relative to the image width [default: 0.2]
+    pub watermark_scale: Option<f32>,
+
+    #[clap(long, value_name = "TEXT")]
+    /// Text to be drawn on the image. Only ASCII characters are supported
+    pub caption: Option<String>,
+
+    #[clap(long, value_enum)]
+    /// Where the caption is placed [default: bottom-left]
+    pub caption_position: Option<Position>,
+
+    #[clap(long, value_name = "PIXELS")]
+    /// Height of caption text, default to 1/30 of the image height
+    pub caption_size: Option<u32>,
+
+    #[clap(long, overrides_with = "no_optimize")]
+    /// Losslessly optimize the encoded PNG. Slower, but usually much smaller
+    /// for screenshots
+    pub optimize: bool,
+
+    #[clap(long, conflicts_with = "optimize_level")]
+    /// Don't optimize, even if enabled in config
+    #[serde(skip)]
+    pub no_optimize: bool,
+
+    #[clap(long, value_enum)]
+    /// How hard the optimizer tries. Implies `--optimize` [default: default]
+    pub optimize_level: Option<OptimizeLevel>,
+
+    #[clap(long, overrides_with = "no_placeholder")]
+    /// Compute BlurHash, dominant color and aspect ratio of the image and
+    /// store them as metadata
+    pub placeholder: bool,
+
+    #[clap(long)]
+    /// Don't compute placeholder, even if enabled in config
+    #[serde(skip)]
+    pub no_placeholder: bool,
+}
+
+impl Process {
+    /// Fill options not given in `self` with those in `defaults`.
+    /// `no_optimize` and `no_placeholder` turn off those of `defaults`.
+    pub fn or(self, defaults: &Process) -> Process {
+        let defaults = defaults.clone();
+        Process {
+            redact: if self.redact.is_empty() {
+                defaults.redact
+            } else {
+                self.redact
+            },
+            redact_style: self.redact_style.or(defaults.redact_style),
+            redact_preset: self.redact_preset.or(defaults.redact_preset),
+            watermark: self.watermark.or(defaults.watermark),
+            watermark_position: self.watermark_position.or(defaults.watermark_position),
+            watermark_opacity: self.watermark_opacity.or(defaults.watermark_opacity),
+            watermark_scale: self.watermark_scale.or(defaults.watermark_scale),
+            caption: self.caption.or(defaults.caption),
+            caption_position: self.caption_position.or(defaults.caption_position),
+            caption_size: self.caption_size.or(defaults.caption_size),
+            optimize: !self.no_optimize && (self.optimize || defaults.optimize),
+            no_optimize: false,
+            optimize_level: match self.no_optimize {
+                true => None,
+                false => self.optimize_level.or(defaults.optimize_level),
+            },
+            placeholder: !self.no_placeholder && (self.placeholder || defaults.placeholder),
+            no_placeholder: false,
+        }
+    }
+
+    /// Whether any option changes the image, or needs it decoded
+    pub fn modifies(&self) -> bool {
+        !self.redact.is_empty()
+            || self.redact_preset.is_some()
+            || self.watermark.is_some()
+            || self.caption.is_some()
+            || self.optimize
+            || self.optimize_level.is_some()
+            || self.placeholder
+    }
+
+    /// Apply all processing steps to `img` in place. Redaction goes first so
+    /// overlays are never obscured.
+    pub fn apply(&self, img: &mut DynamicImage) -> Result<()> {
+        self.redact(img)?;
+        if let Some(ref path) = self.watermark {
+            self.draw_watermark(img, path)?;
+        }
+        if let Some(ref text) = self.caption {
+            self.draw_caption(img, text);
+        }
+        Ok(())
+    }
+
+    /// Compute the placeholder of the processed image, if enabled
+    pub fn placeholder(&self, img: &DynamicImage) -> Result<Option<Placeholder>> {
+        self.placeholder
+            .then(|| Placeholder::from_image(img))
+            .transpose()
+    }
+
+    /// Encode the processed image into PNG
+    pub fn encode(&self, img: &DynamicImage) -> Result<Vec<u8>> {
+        match (self.optimize, self.optimize_level) {
+            (false, None) => encode_png(img),
+            (_, level) => optimize_png(img, level.unwrap_or_default()),
+        }
+    }
+
+    fn redact(&self, img: &mut DynamicImage) -> Result<()> {
+        let preset = self
+            .redact_preset
+            .as_ref()
+            .map(RedactPreset::from_path)
+            .transpose()?
+            .unwrap_or_default();
+
+        let style = self.redact_style.or(preset.style).unwrap_or_default();
+
+        preset
+            .regions
+            .iter()
+            .chain(self.redact.iter())
+            .try_for_each(|region| region.redact(img, style))
+    }
+
+    fn draw_watermark(&self, img: &mut DynamicImage, path: &PathBuf) -> Result<()> {
+        let opacity = self.watermark_opacity.unwrap_or(0.5).clamp(0.0, 1.0);
+        let scale = self.watermark_scale.unwrap_or(0.2);
+        if scale <= 0.0 {
+            return Err(eyre!("Watermark scale should be positive, got {scale}"));
+        }
+
+        let mark = Reader::open(path)
+            .wrap_err_with(|| format!("Failed to open watermark {}", path.display()))?
+            .decode()
+            .map_err(ShotError::Decode)
+            .wrap_err("Unsupported watermark format")?;
+
+        let (width, height) = img.dimensions();
+        let w = ((width as f32 * scale) as u32).max(1);
+        let h = ((mark.height() as f32 * w as f32 / mark.width() as f32) as u32).max(1);
+        debug!("Watermark: {w} x {h}, opacity {opacity}");
+
+        let mut mark = mark.resize_exact(w, h, FilterType::Lanczos3).into_rgba8();
+        mark.pixels_mut()
+            .for_each(|px| px[3] = (px[3] as f32 * opacity) as u8);
+
+        let position = self.watermark_position.unwrap_or(Position::BottomRight);
+        let (x, y) = position.place((width, height), (w, h));
+        imageops::overlay(img, &mark, x, y);
+        Ok(())
+    }
+
+    fn draw_caption(&self, img: &mut DynamicImage, text: &str) {
+        let (width, height) = img.dimensions();
+        let size = self.caption_size.unwrap_or(height / 30).max(8);
+
+        let mask = render_text(text);
+        let ratio = size as f32 / FONT_10X20.character_size.height as f32;
+        let (w, h) = (
+            (mask.width() as f32 * ratio) as u32,
+            (mask.height() as f32 * ratio) as u32,
+        );
+        let mask = imageops::resize(&mask, w.max(1), h.max(1), FilterType::Nearest);
+
+        // Text is drawn in white on a translucent black box to be readable on
+        // any background
+        let pad = size / 3;
+        let mut patch = RgbaImage::from_pixel(w + pad * 2, h + pad * 2, Rgba([0, 0, 0, 160]));
+        mask.enumerate_pixels()
+            .filter(|(_, _, px)| px[0] > 0)
+            .for_each(|(x, y, _)| patch.put_pixel(x + pad, y + pad, Rgba([255; 4])));
+
+        let position = self.caption_position.unwrap_or(Position::BottomLeft);
+        let (x, y) = position.place((width, height), patch.dimensions());
+        imageops::overlay(img, &patch, x, y);
+    }
+}
+
+/// Render `text` with the built-in monospace font into a mask, where drawn
+/// pixels are 255.
+fn render_text(text: &str) -> GrayImage {
+    struct Mask(GrayImage);
+
+    impl OriginDimensions for Mask {
+        fn size(&self) -> Size {
+            Size::new(self.0.width(), self.0.height())
+        }
+    }
+
+    impl DrawTarget for Mask {
+        type Color = BinaryColor;
+        type Error = Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            let (width, height) = self.0.dimensions();
+            for Pixel(p, color) in pixels {
+                let (Ok(x), Ok(y)) = (u32::try_from(p.x), u32::try_from(p.y)) else {
+                    continue;
+                };
+                if color.is_on() && x < width && y < height {
+                    self.0.put_pixel(x, y, Luma([255]));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let text = Text::with_baseline(text, Point::zero(), style, Baseline::Top);
+    let bbox = embedded_graphics::geometry::Dimensions::bounding_box(&text);
+
+    let mut mask = Mask(GrayImage::new(
+        bbox.size.width.max(1),
+        bbox.size.height.max(1),
+    ));
+    let Ok(_) = text.draw(&mut mask);
+    mask.0
+}
+
+/// Placement of an overlay on the image
+#[derive(Ser, De, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl Position {
+    /// Top-left corner of an overlay of size `overlay` in an image of size
+    /// `image`, with a small margin to the edges
+    fn place(self, image: (u32, u32), overlay: (u32, u32)) -> (i64, i64) {
+        let (iw, ih) = (image.0 as i64, image.1 as i64);
+        let (ow, oh) = (overlay.0 as i64, overlay.1 as i64);
+        let margin = iw.min(ih) / 50;
+
+        let (left, top) = (margin, margin);
+        let (right, bottom) = (iw - ow - margin, ih - oh - margin);
+        match self {
+            Position::TopLeft => (left, top),
+            Position::TopRight => (right, top),
+            Position::BottomLeft => (left, bottom),
+            Position::BottomRight => (right, bottom),
+            Position::Center => ((iw - ow) / 2, (ih - oh) / 2),
+        }
+    }
+}
+
+/// A rectangular region of an image, in pixels. Format: `x,y,w,h`
+#[derive(Ser, De, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Region {
+    /// Clamp the region into an image of size `width` x `height`. Returns
+    /// `None` if nothing is left.
+    fn clamp(self, width: u32, height: u32) -> Option<Self> {
+        if self.x >= width || self.y >= height {
+            return None;
+        }
+        let w = self.w.min(width - self.x);
+        let h = self.h.min(height - self.y);
+        (w > 0 && h > 0).then_some(Self { w, h, ..self })
+    }
+
+    fn redact(&self, img: &mut DynamicImage, style: RedactStyle) -> Result<()> {
+        let (width, height) = img.dimensions();
+        let Some(Region { x, y, w, h }) = self.clamp(width, height) else {
+            warn!("Redact region {self} is outside of the image ({width} x {height}), skipped");
+            return Ok(());
+        };
+        debug!("Redacting {x},{y},{w},{h} with {style:?}");
+
+        let patch = match style {
+            RedactStyle::Blur => {
+                // Strong enough that text in the region is unreadable
+                let sigma = (w.max(h) as f32 / 4.0).max(10.0);
+                img.crop_imm(x, y, w, h).blur(sigma)
+            }
+            RedactStyle::Pixelate => {
+                let block = (w.min(h) / 4).max(8);
+                img.crop_imm(x, y, w, h)
+                    .resize_exact((w / block).max(1), (h / block).max(1), FilterType::Triangle)
+                    .resize_exact(w, h, FilterType::Nearest)
+            }
+            RedactStyle::Fill => {
+                for px in x..x + w {
+                    for py in y..y + h {
+                        img.put_pixel(px, py, Rgba([0, 0, 0, 255]));
+                    }
+                }
+                return Ok(());
+            }
+        };
+
+        imageops::replace(img, &patch, x.into(), y.into());
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.w, self.h)
+    }
+}
+
+impl FromStr for Region {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let nums = s
+            .split(',')
+            .map(|n| n.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err("Parse failed. Format: X,Y,W,H")?;
+        let [x, y, w, h] = nums[..] else {
+            return Err(eyre!("Parse failed. Format: X,Y,W,H"));
+        };
+        Ok(Self { x, y, w, h })
+    }
+}
+
+#[derive(Ser, De, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum RedactStyle {
+    /// Gaussian blur
+    #[default]
+    Blur,
+    /// Large, coarse pixels
+    Pixelate,
+    /// Solid black box
+    Fill,
+}
+
+/// Saved redact regions, stored as RON.
+///
+/// ```ron
+/// (
+///     style: Some(Fill),
+///     regions: [(x: 0, y: 0, w: 400, h: 40)],
+/// )
+/// ```
+#[derive(Ser, De, Debug, Default)]
+pub struct RedactPreset {
+    #[serde(default)]
+    pub style: Option<RedactStyle>,
+    pub regions: Vec<Region>,
+}
+
+impl RedactPreset {
+    pub fn from_path(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Unable to read redact preset {}", path.display()))?;
+        ron::from_str(&content)
+            .wrap_err("Expected format: `(regions: [(x: 0, y: 0, w: 1, h: 1)])`")
+            .wrap_err_with(|| format!("Bad redact preset {}", path.display()))
+    }
+}
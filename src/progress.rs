@@ -0,0 +1,67 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use color_eyre::owo_colors::OwoColorize;
+
+/// Upload progress with throughput and ETA, drawn on stderr
+pub struct ProgressBar {
+    start: Instant,
+    /// When the bar was last drawn, `None` if never
+    drawn: Mutex<Option<Instant>>,
+}
+
+impl ProgressBar {
+    const WIDTH: u64 = 30;
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    /// `None` if stderr is not a terminal, so logs and pipes stay clean
+    pub fn new() -> Option<Self> {
+        io::stderr().is_terminal().then(|| Self {
+            start: Instant::now(),
+            drawn: Mutex::new(None),
+        })
+    }
+
+    pub fn update(&self, sent: u64, total: u64) {
+        let mut drawn = self.drawn.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if matches!(*drawn, Some(at) if now - at < Self::INTERVAL) && sent < total {
+            return;
+        }
+        *drawn = Some(now);
+
+        let total = total.max(1);
+        let filled = (sent * Self::WIDTH / total).min(Self::WIDTH) as usize;
+        let elapsed = self.start.elapsed().as_secs_f64().max(0.001);
+        let speed = sent as f64 / elapsed;
+        let eta = if speed > 0. {
+            let secs = (total.saturating_sub(sent) as f64 / speed).ceil();
+            humantime::format_duration(Duration::from_secs(secs as u64)).to_string()
+        } else {
+            "-".to_owned()
+        };
+
+        eprint!(
+            "\r\x1b[2K [{}{}] {:>3}%  {} / {}  {}/s  ETA {}",
+            "=".repeat(filled).green(),
+            " ".repeat(Self::WIDTH as usize - filled),
+            sent * 100 / total,
+            bytesize::to_string(sent, true),
+            bytesize::to_string(total, true),
+            bytesize::to_string(speed as u64, true),
+            eta
+        );
+        io::stderr().flush().ok();
+    }
+}
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        if self.drawn.get_mut().map_or(true, |x| x.is_some()) {
+            eprint!("\r\x1b[2K");
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use attohttpc::{
+    body::Body,
+    header::{HeaderValue, RETRY_AFTER},
+    ErrorKind, RequestBuilder, StatusCode,
+};
+use clap::Args;
+use log::{info, warn};
+use serde::{de::Error as _, Deserialize as De, Deserializer, Serialize as Ser};
+
+use crate::ShotError;
+
+/// How failed requests are retried and how fast requests are sent.
+///
+/// Also used in [`Config`](crate::Config) as defaults, where flags given on
+/// the command line take precedence.
+#[derive(Args, Ser, De, Debug, Clone, Default)]
+#[clap(about = None, long_about = None)]
+#[group(skip)]
+#[serde(default)]
+pub struct Retry {
+    #[clap(long, global = true, value_name = "N")]
+    /// Times to retry on connection errors, 5xx and 429 responses
+    /// [default: 3]
+    pub retries: Option<u32>,
+
+    #[clap(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    /// Delay before the first retry, doubled after each one [default: 500ms]
+    #[serde(with = "humantime_serde")]
+    pub retry_delay: Option<Duration>,
+
+    #[clap(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    /// Maximum delay between retries [default: 30s]
+    #[serde(with = "humantime_serde")]
+    pub retry_max_delay: Option<Duration>,
+
+    #[clap(long, global = true, value_name = "PER_SECOND", value_parser = parse_rate_limit)]
+    /// Maximum number of requests sent per second, at least one per hour
+    /// (0.0003) [default: unlimited]
+    #[serde(deserialize_with = "de_rate_limit")]
+    pub rate_limit: Option<f64>,
+}
+
+/// Longest interval between requests of a [`RateLimiter`], one hour
+const MAX_INTERVAL: Duration = Duration::from_secs(3600);
+
+fn check_rate_limit(per_second: f64) -> Result<f64, String> {
+    if per_second.is_finite() && per_second >= 1. / MAX_INTERVAL.as_secs_f64() {
+        Ok(per_second)
+    } else {
+        Err(format!(
+            "rate limit must be at least one request per hour (0.0003), got {per_second}"
+        ))
+    }
+}
+
+fn parse_rate_limit(s: &str) -> Result<f64, String> {
+    check_rate_limit(s.parse().map_err(|e| format!("{e}"))?)
+}
+
+fn de_rate_limit<'de, D: Deserializer<'de>>(d: D) -> Result<Option<f64>, D::Error> {
+    Option::<f64>::deserialize(d)?
+        .map(check_rate_limit)
+        .transpose()
+        .map_err(D::Error::custom)
+}
+
+impl Retry {
+    /// Fill options not given in `self` with those in `defaults`
+    pub fn or(self, defaults: &Retry) -> Retry {
+        let defaults = defaults.clone();
+        Retry {
+            retries: self.retries.or(defaults.retries),
+            retry_delay: self.retry_delay.or(defaults.retry_delay),
+            retry_max_delay: self.retry_max_delay.or(defaults.retry_max_delay),
+            rate_limit: self.rate_limit.or(defaults.rate_limit),
+        }
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3)
+    }
+
+    /// Longest delay between retries, including those asked by servers
+    pub fn max_delay(&self) -> Duration {
+        self.retry_max_delay.unwrap_or(Duration::from_secs(30))
+    }
+
+    /// Delay before retry number `attempt`, starting from 0. Exponential
+    /// with "equal jitter", so concurrent clients don't retry in lockstep.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let base = self.retry_delay.unwrap_or(Duration::from_millis(500));
+        let delay = base
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay());
+        delay / 2 + delay.mul_f64(random() / 2.)
+    }
+
+    /// Delay before retrying `outcome`, or `None` if it should not be
+    /// retried.
+    ///
+    /// Requests that are not `idempotent` may have taken effect even if they
+    /// failed, so they are only retried when the server surely didn't process
+    /// them: connection refused and 429.
+    pub fn delay(&self, attempt: u32, outcome: &Outcome, idempotent: bool) -> Option<Duration> {
+        if attempt >= self.retries() {
+            return None;
+        }
+        let reason = match outcome {
+            Outcome::Response {
+                status,
+                retry_after,
+            } => {
+                let retryable = *status == StatusCode::TOO_MANY_REQUESTS
+                    || (idempotent && status.is_server_error());
+                if !retryable {
+                    return None;
+                }
+                if let Some(after) = retry_after {
+                    // Not trusted to keep us waiting for hours
+                    let after = (*after).min(self.max_delay());
+                    warn!(
+                        "Server responded {status}, retrying in {}s ({}/{})",
+                        after.as_secs(),
+                        attempt + 1,
+                        self.retries()
+                    );
+                    return Some(after);
+                }
+                status.to_string()
+            }
+            Outcome::Error { message, sent } if idempotent || !sent => message.clone(),
+            Outcome::Error { .. } => {
+                info!(
+                    "Not retrying as the request may have been received. Set a custom ID to \
+                     make uploads safe to retry."
+                );
+                return None;
+            }
+        };
+        let delay = self.backoff(attempt);
+        warn!(
+            "Request failed ({reason}), retrying in {}ms ({}/{})",
+            delay.as_millis(),
+            attempt + 1,
+            self.retries()
+        );
+        Some(delay)
+    }
+}
+
+impl Retry {
+    /// Send request built by `req`, retrying according to this. `req` is
+    /// called once per attempt, after waiting for `limiter`.
+    pub(crate) fn send<B: Body>(
+        &self,
+        limiter: &RateLimiter,
+        idempotent: bool,
+        req: impl Fn() -> Result<RequestBuilder<B>, ShotError>,
+    ) -> Result<attohttpc::Response, ShotError> {
+        let mut attempt = 0;
+        loop {
+            limiter.wait();
+            let outcome = req()?.send();
+            match self.delay(attempt, &(&outcome).into(), idempotent) {
+                Some(delay) => thread::sleep(delay),
+                None => return Ok(outcome?),
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// What came of a request, as far as retrying is concerned. Converted from
+/// results of both blocking and async clients.
+#[derive(Debug)]
+pub enum Outcome {
+    /// Server responded, `retry_after` is the delay it asked for
+    Response {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    /// No response. `sent` is false if the request surely didn't reach the
+    /// server.
+    Error { message: String, sent: bool },
+}
+
+impl From<&attohttpc::Result<attohttpc::Response>> for Outcome {
+    fn from(outcome: &attohttpc::Result<attohttpc::Response>) -> Self {
+        match outcome {
+            Ok(res) => Outcome::Response {
+                status: res.status(),
+                retry_after: retry_after(res.headers().get(RETRY_AFTER)),
+            },
+            Err(e) => Outcome::Error {
+                message: e.to_string(),
+                sent: !not_connected(e.kind()),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<&reqwest::Result<reqwest::Response>> for Outcome {
+    fn from(outcome: &reqwest::Result<reqwest::Response>) -> Self {
+        match outcome {
+            Ok(res) => Outcome::Response {
+                status: StatusCode::from_u16(res.status().as_u16())
+                    .expect("status from response is valid"),
+                retry_after: retry_after(res.headers().get(RETRY_AFTER.as_str())),
+            },
+            // Connect errors include DNS and TLS, all before sending
+            Err(e) => Outcome::Error {
+                message: e.to_string(),
+                sent: !e.is_connect(),
+            },
+        }
+    }
+}
+
+/// Delay asked by the server. Only the delay-seconds form is supported,
+/// which is what Cloudflare sends.
+fn retry_after(value: Option<&HeaderValue>) -> Option<Duration> {
+    value?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Whether the error happened before the request was sent
+fn not_connected(kind: &ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::Io(e) if matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::AddrNotAvailable
+                | io::ErrorKind::NotConnected
+        )
+    )
+}
+
+/// Random number in `[0, 1)`. Good enough for jitter, and saves a dependency.
+fn random() -> f64 {
+    // Every `RandomState` is seeded differently
+    let hash = RandomState::new().build_hasher().finish();
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Spaces requests evenly to stay under a rate. Shared by all requests of an
+/// [`API`](crate::API), so batch operations are limited as a whole.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Option<Duration>,
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: Option<f64>) -> Self {
+        Self {
+            interval: per_second.filter(|x| *x > 0.).map(|x| {
+                Duration::try_from_secs_f64(1. / x)
+                    .unwrap_or(MAX_INTERVAL)
+                    .min(MAX_INTERVAL)
+            }),
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Block until next request is allowed
+    pub fn wait(&self) {
+        if let Some(delay) = self.reserve() {
+            thread::sleep(delay)
+        }
+    }
+
+    /// Book the next free slot, and return how long to wait for it. For
+    /// callers that can't block, e.g. async ones.
+    pub fn reserve(&self) -> Option<Duration> {
+        let interval = self.interval?;
+        let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let at = next.filter(|at| *at > now).unwrap_or(now);
+        *next = Some(at + interval);
+        Some(at - now).filter(|x| !x.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_clamped() {
+        let retry = Retry {
+            retry_max_delay: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let outcome = |secs| Outcome::Response {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(Duration::from_secs(secs)),
+        };
+        assert_eq!(
+            retry.delay(0, &outcome(3), true),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            retry.delay(0, &outcome(36000), true),
+            Some(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn rate_limit_range() {
+        assert_eq!(parse_rate_limit("2.5"), Ok(2.5));
+        for bad in ["0", "-1", "1e-20", "inf", "NaN", "x"] {
+            assert!(parse_rate_limit(bad).is_err(), "{bad}");
+        }
+        assert!(ron::from_str::<Retry>("(rate_limit: Some(1e-20))").is_err());
+        assert!(ron::from_str::<Retry>("(rate_limit: Some(4.0))").is_ok());
+
+        let limiter = RateLimiter::new(Some(1e-20));
+        assert_eq!(limiter.interval, Some(MAX_INTERVAL));
+    }
+}
@@ -1,43 +1,88 @@
 use std::time;
 
+#[cfg(feature = "clipboard")]
 use arboard::ImageData;
-use color_eyre::{
-    config::HookBuilder,
-    eyre::{Context, ContextCompat},
-    owo_colors::OwoColorize,
-    Result,
-};
+use clap::ValueEnum;
+use color_eyre::{owo_colors::OwoColorize, Result};
 use humantime::{format_rfc3339, format_rfc3339_seconds};
-use image::{codecs::png::PngEncoder, ColorType, EncodableLayout, ImageBuffer, ImageEncoder, Rgba};
-use log::{error, info};
+use image::{codecs::png::PngEncoder, DynamicImage, GenericImageView, ImageEncoder, ImageFormat};
+use log::{info, warn};
+use serde::{Deserialize as De, Serialize as Ser};
+use serde_json::Value;
 use url::Url;
 
-use crate::{Image, Response};
+use crate::{Image, Placeholder, ShotError};
 
+/// Name of the binary, used in hints
+pub(crate) const BIN_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// How results are printed
+#[derive(Ser, De, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// Colored, aligned text
+    #[default]
+    Human,
+    /// JSON on stdout, for scripts
+    Json,
+}
+
+/// Encode clipboard pixels into PNG
+#[cfg(feature = "clipboard")]
 pub fn image_data_to_png(data: &ImageData) -> Result<Vec<u8>> {
-    let size = data.bytes.len();
+    encode_png(&image_data_to_image(data)?)
+}
+
+/// Convert raw clipboard pixels into an in-memory image, ready to be
+/// processed and encoded
+#[cfg(feature = "clipboard")]
+pub fn image_data_to_image(data: &ImageData) -> Result<DynamicImage> {
+    use color_eyre::eyre::{Context, ContextCompat};
+    use image::{ImageBuffer, Rgba};
+
     let width: u32 = data.width.try_into().wrap_err("Image width too big")?;
     let height: u32 = data.height.try_into().wrap_err("Image height too big")?;
-    let img = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, data.bytes.as_ref())
+    let img = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, data.bytes.to_vec())
         .wrap_err("Bad `ImageData`")
         .wrap_err("Unable to convert raw pixels to encodable RgbaImage")?;
-    let mut buf = Vec::with_capacity(size);
-    PngEncoder::new(&mut buf).write_image(img.as_bytes(), width, height, ColorType::Rgba8)?;
+
+    Ok(DynamicImage::ImageRgba8(img))
+}
+
+/// Encode `img` into PNG with default settings, see
+/// [`Process::encode`](crate::Process::encode) for optimized output
+pub fn encode_png(img: &DynamicImage) -> Result<Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let mut buf = Vec::with_capacity(img.as_bytes().len());
+    PngEncoder::new(&mut buf)
+        .write_image(img.as_bytes(), width, height, img.color())
+        .map_err(|e| ShotError::Encode(e.into()))?;
 
     Ok(buf)
 }
 
+/// MIME type of `format`, if Cloudflare Images accepts it
+pub fn mime_type(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Png => Some("image/png"),
+        ImageFormat::Jpeg => Some("image/jpeg"),
+        ImageFormat::Gif => Some("image/gif"),
+        ImageFormat::WebP => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Default name of pasted images, upload time in RFC 3339
 pub fn image_name() -> String {
     let now = time::SystemTime::now();
     format_rfc3339_seconds(now).to_string() + ".png"
 }
 
-pub fn display_title(title: &str, space: usize) {
+pub(crate) fn display_title(title: &str, space: usize) {
     println!();
     display_aligned("", &title.bold().to_string(), space)
 }
 
-pub fn display_aligned(k: &str, v: &str, space: usize) {
+pub(crate) fn display_aligned(k: &str, v: &str, space: usize) {
     println!(" {:>space$}  {}", k.blue().bold(), v)
 }
 
@@ -48,101 +93,78 @@ pub fn format_html_url(url: &Url, filename: &str) -> String {
     format!("<img alt=\"{}\" src=\"{}\" />", filename, url.as_str())
 }
 
-mod logger {
-    use color_eyre::{eyre::Context, Result};
-    use env_logger::{
-        filter::Builder,
-        fmt::{Color, Style, StyledValue},
-    };
-    use log::Level;
-
-    pub fn init_logger() -> Result<()> {
-        let mut builder = env_logger::Builder::new();
-
-        builder
-            .format(|f, record| {
-                use std::io::Write;
-
-                let mut style = f.style();
-                let level = colored_level(&mut style, record.level());
-
-                writeln!(f, " {}  {}", level, record.args())
-            })
-            .filter_level({
-                match ::std::env::var("SHOT_LOG") {
-                    Ok(filter) => Builder::default().parse(&filter).build().filter(),
-                    Err(_) => log::LevelFilter::Info,
-                }
-            })
-            .try_init()
-            .wrap_err("Failed to init logger")
-    }
+impl Image {
+    /// Print the uploaded image in format of `output`. If `variant` is set,
+    /// only that variant is shown.
+    pub fn print(
+        &self,
+        output: Output,
+        variant: Option<&str>,
+        placeholder: Option<&Placeholder>,
+    ) -> Result<()> {
+        #[derive(Ser)]
+        struct Uploaded<'a> {
+            #[serde(flatten)]
+            image: &'a Image,
+            /// Url of the chosen variant
+            #[serde(skip_serializing_if = "Option::is_none")]
+            url: Option<&'a Url>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            placeholder: Option<&'a Placeholder>,
+        }
 
-    fn colored_level(style: &mut Style, level: Level) -> StyledValue<&'static str> {
-        match level {
-            Level::Trace => style
-                .set_bold(true)
-                .set_color(Color::Magenta)
-                .value("TRACE"),
-            Level::Debug => style.set_bold(true).set_color(Color::Blue).value("DEBUG"),
-            Level::Info => style.set_bold(true).set_color(Color::Green).value(" INFO"),
-            Level::Warn => style.set_bold(true).set_color(Color::Yellow).value(" WARN"),
-            Level::Error => style.set_bold(true).set_color(Color::Red).value("ERROR"),
+        match output {
+            Output::Human => self.log(variant),
+            Output::Json => {
+                let url = variant.and_then(|v| self.variant(v));
+                let json = serde_json::to_string_pretty(&Uploaded {
+                    image: self,
+                    url,
+                    placeholder,
+                })?;
+                println!("{json}")
+            }
         }
+        Ok(())
     }
-}
-
-pub use logger::init_logger;
 
-impl Response<Image> {
-    pub fn log(&self) {
-        if let Some(ref msgs) = self.messages {
-            msgs.iter()
-                .for_each(|msg| info!("{}  {}", "Message".blue(), msg))
+    pub fn log(&self, variant: Option<&str>) {
+        let space = 5;
+        info!("Image uploaded.");
+        display_title("General", space);
+        display_aligned("ID", &self.id, space);
+        display_aligned("Name", &self.filename, space);
+        display_aligned("Time", &format_rfc3339(self.uploaded).to_string(), space);
+        if let Some(ref url) = self.deletion_url {
+            display_aligned("Del", url.as_str(), space);
         }
 
-        if !self.success {
-            error!("API returned an error:");
-
-            self.errors
-                .iter()
-                .for_each(|err| error!("(Code {}) {}", err.code.red(), err.message));
-        } else if let Some(ref img) = self.result {
-            let space = 5;
-            info!("Image uploaded.");
-            display_title("General", space);
-            display_aligned("ID", &img.id, space);
-            display_aligned("Name", &img.filename, space);
-            display_aligned("Time", &format_rfc3339(img.uploaded).to_string(), space);
-
-            if let Some(ref md) = img.meta {
-                if !md.is_empty() {
-                    display_title("Metadata", space);
-                    md.iter().for_each(|(k, v)| display_aligned(k, v, space))
-                }
+        if let Some(ref md) = self.meta {
+            if !md.is_empty() {
+                display_title("Metadata", space);
+                md.iter().for_each(|(k, v)| match v {
+                    Value::String(v) => display_aligned(k, v, space),
+                    v => display_aligned(k, &v.to_string(), space),
+                })
             }
+        }
 
-            img.variants.iter().for_each(|url| {
-                let variant = url
-                    .path_segments()
-                    .and_then(|x| x.last())
-                    .unwrap_or("UNKNOWN");
-
-                display_title(&format!("Variant {}", variant.green()), space);
-                display_aligned("Url", url.as_str(), space);
-                display_aligned("HTML", &format_html_url(url, &img.filename), space);
-                display_aligned("MD", &format_markdown_url(url, &img.filename), space);
-            })
-        } else {
-            error!("Bad response: {:#?}", self)
+        let urls = match variant.map(|v| (v, self.variant(v))) {
+            Some((_, Some(url))) => vec![url],
+            Some((v, None)) => {
+                warn!("Variant `{v}` not found, showing all variants");
+                self.variants.iter().collect()
+            }
+            None => self.variants.iter().collect(),
         };
-    }
-}
 
-pub fn init() -> Result<()> {
-    HookBuilder::default()
-        .display_env_section(false)
-        .install()?;
-    init_logger()?;
-    Ok(())
+        urls.into_iter().for_each(|url| {
+            let variant = Image::variant_name(url);
+
+            display_title(&format!("Variant {}", variant.green()), space);
+            display_aligned("Url", url.as_str(), space);
+            display_aligned("HTML", &format_html_url(url, &self.filename), space);
+            display_aligned("MD", &format_markdown_url(url, &self.filename), space);
+        })
+    }
 }
@@ -0,0 +1,355 @@
+//! Blocking client against the mock server
+
+use std::time::Duration;
+
+use shot::{Backend, Fault, MockServer, Retry, ShotError, API};
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\nnot really a png";
+
+fn mock() -> (MockServer, API) {
+    let mock = MockServer::bind("127.0.0.1:0").unwrap();
+    mock.spawn();
+    let api = api(&mock);
+    (mock, api)
+}
+
+fn api(mock: &MockServer) -> API {
+    let retry = Retry {
+        retry_delay: Some(Duration::from_millis(1)),
+        ..Default::default()
+    };
+    API::new(mock.auth())
+        .unwrap()
+        .with_client(&mock.client())
+        .unwrap()
+        .with_retry(retry)
+}
+
+fn fail(mock: &MockServer, status: u16, times: u32) {
+    mock.fail(Fault { status, times })
+}
+
+#[test]
+fn upload_and_get() {
+    let (mock, api) = mock();
+    let mut upload = api.upload("a.png", PNG).id("dir/a");
+    upload.add_meta("key", "value").add_meta("n", 1);
+    let image = upload.send().unwrap();
+
+    assert_eq!(image.id, "dir/a");
+    assert_eq!(image.filename, "a.png");
+    assert!(!image.require_signed_urls);
+    assert_eq!(image.meta.as_ref().unwrap()["key"], "value");
+    assert_eq!(image.meta.as_ref().unwrap()["n"], 1);
+    assert!(image.variant("public").is_some());
+    assert_eq!(mock.blob("dir/a").unwrap(), PNG);
+
+    let got = api.get("dir/a").unwrap();
+    assert_eq!(got.filename, "a.png");
+    assert_eq!(got.uploaded, image.uploaded);
+}
+
+#[test]
+fn upload_without_id() {
+    let (mock, api) = mock();
+    let image = api.upload("a.png", PNG).send().unwrap();
+    assert_eq!(image.id.len(), 36);
+    assert_eq!(mock.images().len(), 1);
+}
+
+#[test]
+fn duplicate_id() {
+    let (_mock, api) = mock();
+    api.upload("a.png", PNG).id("a").send().unwrap();
+    let err = api.upload("b.png", PNG).id("a").send().unwrap_err();
+    assert_eq!(err.codes(), [5409]);
+    assert_eq!(api.get("a").unwrap().filename, "a.png");
+}
+
+#[test]
+fn list_pages() {
+    let (_mock, api) = mock();
+    for i in 0..15 {
+        api.upload("a.png", PNG).id(&i.to_string()).send().unwrap();
+    }
+    let first = api.list(1, 10).unwrap();
+    let second = api.list(2, 10).unwrap();
+    assert_eq!(first.len(), 10);
+    assert_eq!(second.len(), 5);
+    assert_eq!(first[0].id, "0");
+    assert_eq!(second[4].id, "14");
+    assert!(api.list(3, 10).unwrap().is_empty());
+}
+
+#[test]
+fn delete() {
+    let (mock, api) = mock();
+    api.upload("a.png", PNG).id("a").send().unwrap();
+    api.delete("a").unwrap();
+    assert!(mock.images().is_empty());
+
+    let err = api.get("a").unwrap_err();
+    assert_eq!(err.codes(), [5404]);
+    assert!(matches!(
+        err,
+        ShotError::Api {
+            status: Some(404),
+            ..
+        }
+    ));
+    assert_eq!(api.delete("a").unwrap_err().codes(), [5404]);
+}
+
+#[test]
+fn stats() {
+    let (_mock, api) = mock();
+    api.upload("a.png", PNG).send().unwrap();
+    api.upload("b.png", PNG).send().unwrap();
+    let stats = api.stats().unwrap();
+    assert_eq!(stats.count.current, 2);
+    assert_eq!(stats.count.allowed, MockServer::ALLOWED);
+}
+
+#[test]
+fn direct_upload() {
+    let (mock, api) = mock();
+    let upload = api.get_direct_upload().unwrap();
+    // Drafts are not images yet
+    assert_eq!(api.stats().unwrap().count.current, 0);
+    assert_eq!(api.get(&upload.id).unwrap_err().codes(), [5404]);
+
+    let form = attohttpc::MultipartBuilder::new()
+        .with_file(
+            attohttpc::MultipartFile::new("file", PNG)
+                .with_filename("direct.png")
+                .with_type("image/png")
+                .unwrap(),
+        )
+        .build()
+        .unwrap();
+    let res = attohttpc::post(upload.upload_url.as_str())
+        .body(form)
+        .send()
+        .unwrap();
+    assert!(res.is_success());
+
+    let image = api.get(&upload.id).unwrap();
+    assert_eq!(image.filename, "direct.png");
+    assert_eq!(mock.blob(&upload.id).unwrap(), PNG);
+
+    // URLs work only once
+    let res = attohttpc::post(upload.upload_url.as_str()).send().unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+fn signed_custom_id() {
+    let (mock, api) = mock();
+    let err = api
+        .upload("a.png", PNG)
+        .id("a")
+        .require_signed_urls()
+        .send()
+        .unwrap_err();
+    assert!(matches!(err, ShotError::Config(_)), "{err:?}");
+
+    // Rejected by the server too
+    let form = attohttpc::MultipartBuilder::new()
+        .with_text("id", "a")
+        .with_text("requireSignedURLs", "true")
+        .with_file(attohttpc::MultipartFile::new("file", PNG).with_filename("a.png"))
+        .build()
+        .unwrap();
+    let res = attohttpc::post(api.url().as_str())
+        .bearer_auth(MockServer::TOKEN)
+        .body(form)
+        .send()
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 400);
+    assert!(res.text().unwrap().contains("requireSignedURLs"));
+    assert!(mock.images().is_empty());
+}
+
+#[test]
+fn delete_direct_upload() {
+    let (_mock, api) = mock();
+    let upload = api.get_direct_upload().unwrap();
+    api.delete(&upload.id).unwrap();
+    assert_eq!(api.delete(&upload.id).unwrap_err().codes(), [5404]);
+}
+
+#[test]
+fn verify_and_diagnose() {
+    let (_mock, api) = mock();
+    assert_eq!(api.verify().unwrap().status, "active");
+    api.diagnose(true).ensure_passed().unwrap();
+    api.diagnose(false).ensure_passed().unwrap();
+}
+
+#[test]
+fn wrong_token() {
+    let mock = MockServer::bind("127.0.0.1:0").unwrap().with_token("right");
+    mock.spawn();
+    api(&mock).stats().unwrap();
+
+    let mut auth = mock.auth();
+    auth.token = "wrong".to_owned();
+    let api = API::new(auth).unwrap().with_client(&mock.client()).unwrap();
+    let err = api.upload("a.png", PNG).send().unwrap_err();
+    assert!(err.is_auth());
+    assert_eq!(err.codes(), [10000]);
+    assert!(mock.images().is_empty());
+}
+
+#[test]
+fn wrong_account() {
+    let mock = MockServer::bind("127.0.0.1:0").unwrap();
+    mock.spawn();
+    let mut auth = mock.auth();
+    auth.account_id = "other".to_owned();
+    let api = API::new(auth).unwrap().with_client(&mock.client()).unwrap();
+    let err = api.stats().unwrap_err();
+    assert_eq!(err.codes(), [7003]);
+    assert!(err.is_auth());
+}
+
+#[test]
+fn retry_server_errors() {
+    let (mock, api) = mock();
+    fail(&mock, 503, 2);
+    api.upload("a.png", PNG).id("a").send().unwrap();
+    assert_eq!(mock.images().len(), 1);
+
+    fail(&mock, 500, 4);
+    let err = api.get("a").unwrap_err();
+    assert!(err.is_transient());
+    assert_eq!(err.codes(), [10001]);
+}
+
+#[test]
+fn no_retry_without_id() {
+    let (mock, api) = mock();
+    fail(&mock, 502, 1);
+    let err = api.upload("a.png", PNG).send().unwrap_err();
+    assert!(err.is_transient());
+    assert!(mock.images().is_empty());
+}
+
+#[test]
+fn retry_rate_limited() {
+    let (mock, api) = mock();
+    fail(&mock, 429, 1);
+    // Retried even without ID, as the upload surely didn't happen
+    api.upload("a.png", PNG).send().unwrap();
+    assert_eq!(mock.images().len(), 1);
+}
+
+#[test]
+fn injected_client_errors() {
+    let (mock, api) = mock();
+    fail(&mock, 401, 1);
+    let err = api.stats().unwrap_err();
+    assert!(err.is_auth());
+
+    fail(&mock, 413, 1);
+    let err = api.upload("a.png", PNG).id("a").send().unwrap_err();
+    let ShotError::Api {
+        status: Some(413),
+        ref errors,
+    } = err
+    else {
+        panic!("{err:?}")
+    };
+    assert_eq!(
+        errors[0].hint(),
+        Some("Image is too large. The limit is 10 MB")
+    );
+    assert!(!err.is_transient());
+    assert!(mock.images().is_empty());
+}
+
+#[test]
+fn limits_checked_before_sending() {
+    let (mock, api) = mock();
+    let big = vec![0; shot::Upload::IMAGE_LIMIT + 1];
+    let err = api.upload("a.png", &big).send().unwrap_err();
+    assert!(matches!(err, ShotError::TooLarge { .. }));
+    assert!(mock.images().is_empty());
+}
+
+#[test]
+fn persistence() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let mock = MockServer::bind("127.0.0.1:0")
+            .unwrap()
+            .with_dir(dir.path())
+            .unwrap();
+        mock.spawn();
+        let api = api(&mock);
+        api.upload("a.png", PNG).id("a/b").send().unwrap();
+        api.upload("c.png", PNG).id("c").send().unwrap();
+        api.delete("c").unwrap();
+    }
+
+    let mock = MockServer::bind("127.0.0.1:0")
+        .unwrap()
+        .with_dir(dir.path())
+        .unwrap();
+    mock.spawn();
+    let image = api(&mock).get("a/b").unwrap();
+    assert_eq!(image.filename, "a.png");
+    assert_eq!(mock.blob("a/b").unwrap(), PNG);
+    assert_eq!(mock.images().len(), 1);
+}
+
+#[test]
+fn parse_fault() {
+    assert_eq!(
+        "503x2".parse::<Fault>().unwrap(),
+        Fault {
+            status: 503,
+            times: 2
+        }
+    );
+    assert_eq!("429".parse::<Fault>().unwrap().times, 1);
+    assert!("200".parse::<Fault>().is_err());
+    assert!("503x".parse::<Fault>().is_err());
+}
+
+#[test]
+fn blob_and_variant() {
+    let (_mock, api) = mock();
+    let image = api.upload("a.png", PNG).id("a").send().unwrap();
+    let mut blob = vec![];
+    assert_eq!(api.blob("a", &mut blob).unwrap(), PNG.len() as u64);
+    assert_eq!(blob, PNG);
+
+    let mut variant = vec![];
+    api.download(image.variant("public").unwrap(), &mut variant)
+        .unwrap();
+    assert_eq!(variant, PNG);
+
+    let err = api.blob("missing", &mut vec![]).unwrap_err();
+    assert_eq!(err.codes(), [5404]);
+
+    let signed = api
+        .upload("b.png", PNG)
+        .require_signed_urls()
+        .send()
+        .unwrap();
+    assert!(signed.require_signed_urls);
+    let err = api
+        .download(signed.variant("public").unwrap(), &mut vec![])
+        .unwrap_err();
+    assert!(matches!(err, ShotError::Status { status: 403, .. }));
+}
+
+#[test]
+fn list_all() {
+    let (_mock, api) = mock();
+    for i in 0..12 {
+        api.upload("a.png", PNG).id(&i.to_string()).send().unwrap();
+    }
+    assert_eq!(api.list_all().unwrap().len(), 12);
+}
@@ -0,0 +1,728 @@
+//! `shot` binary against the mock server
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use serde_json::Value;
+use shot::{Fault, MockServer};
+use tempfile::TempDir;
+
+/// Mock server and a home directory with its own config
+struct Shot {
+    mock: MockServer,
+    home: TempDir,
+}
+
+impl Shot {
+    fn new() -> Self {
+        let mock = MockServer::bind("127.0.0.1:0").unwrap();
+        mock.spawn();
+        Self {
+            mock,
+            home: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    /// With auth of the mock server in config
+    fn authed() -> Self {
+        let shot = Self::new();
+        let auth = shot.mock.auth();
+        shot.ok(&["auth", &auth.account_id, &auth.token]);
+        shot
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.home.path().join("shot.ron")
+    }
+
+    fn cmd(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_shot"));
+        cmd.args(args)
+            .args(["--retry-delay", "1ms"])
+            .current_dir(self.home.path())
+            .env("HOME", self.home.path())
+            .env("XDG_CONFIG_HOME", self.home.path())
+            .env("SHOT_CONFIG", self.config_path())
+            .env("SHOT_API_BASE", self.mock.url().as_str())
+            .env("RUST_BACKTRACE", "0")
+            .env_remove("SHOT_PROFILE")
+            .env_remove("SHOT_ACCOUNT_ID")
+            .env_remove("SHOT_API_TOKEN")
+            .stdin(Stdio::null());
+        cmd
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.cmd(args).output().unwrap()
+    }
+
+    /// Run and expect success, returning stdout
+    fn ok(&self, args: &[&str]) -> String {
+        let out = self.run(args);
+        assert!(
+            out.status.success(),
+            "`shot {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr)
+        );
+        String::from_utf8(out.stdout).unwrap()
+    }
+
+    /// Run and expect exit code `code`
+    fn fails(&self, args: &[&str], code: i32) -> String {
+        let out = self.run(args);
+        let stderr = String::from_utf8_lossy(&out.stderr).into_owned();
+        assert_eq!(out.status.code(), Some(code), "{stderr}");
+        stderr
+    }
+
+    fn json(&self, args: &[&str]) -> Value {
+        serde_json::from_str(&self.ok(args)).unwrap()
+    }
+
+    /// A small PNG image in the home directory
+    fn image(&self, name: &str) -> PathBuf {
+        let path = self.home.path().join(name);
+        image::RgbImage::from_pixel(8, 6, image::Rgb([200, 10, 10]))
+            .save(&path)
+            .unwrap();
+        path
+    }
+}
+
+fn str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn auth() {
+    let shot = Shot::new();
+    let auth = shot.mock.auth();
+    shot.ok(&["auth", &auth.account_id, &auth.token]);
+    let config = fs::read_to_string(shot.config_path()).unwrap();
+    assert!(config.contains(MockServer::ACCOUNT_ID));
+}
+
+#[test]
+fn auth_rejected() {
+    let shot = Shot::new();
+    let stderr = shot.fails(&["auth", MockServer::ACCOUNT_ID, "wrong"], 1);
+    assert!(stderr.contains("Unable to verify the auth pair"));
+    assert!(!shot.config_path().exists());
+}
+
+#[test]
+fn auth_dry_run() {
+    let shot = Shot::new();
+    let auth = shot.mock.auth();
+    shot.ok(&["--dry-run", "auth", &auth.account_id, &auth.token]);
+    assert!(!shot.config_path().exists());
+}
+
+#[test]
+fn config() {
+    let shot = Shot::authed();
+    assert_eq!(
+        shot.ok(&["config", "path"]).trim(),
+        str(&shot.config_path())
+    );
+
+    shot.ok(&["config", "set", "profiles.default.variant", "thumb"]);
+    assert_eq!(
+        shot.ok(&["config", "get", "profiles.default.variant"])
+            .trim(),
+        "thumb"
+    );
+    let list = shot.ok(&["config", "list"]);
+    assert!(list.contains("thumb"));
+    assert!(!list.contains(MockServer::TOKEN));
+
+    shot.ok(&["config", "unset", "profiles.default.variant"]);
+    assert!(!shot.ok(&["config", "list"]).contains("thumb"));
+}
+
+#[test]
+fn config_edit() {
+    let shot = Shot::authed();
+    let before = fs::read_to_string(shot.config_path()).unwrap();
+    let out = shot
+        .cmd(&["config", "edit"])
+        .env("VISUAL", "true")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(fs::read_to_string(shot.config_path()).unwrap(), before);
+}
+
+#[test]
+fn config_import() {
+    let shot = Shot::authed();
+    let sxcu = shot.home.path().join("host.sxcu");
+    fs::write(
+        &sxcu,
+        r#"{
+            "DestinationType": "ImageUploader",
+            "RequestMethod": "POST",
+            "RequestURL": "https://example.com/upload",
+            "FileFormName": "image",
+            "Headers": {"X-Api-Key": "secret-key"},
+            "URL": "$json:data.url$"
+        }"#,
+    )
+    .unwrap();
+    shot.ok(&["config", "import", str(&sxcu)]);
+    let backend = shot.ok(&["config", "get", "profiles.host.backend"]);
+    assert!(backend.contains("https://example.com/upload"));
+    assert!(backend.contains("{json:data.url}"));
+
+    let list = shot.ok(&["config", "list"]);
+    assert!(
+        list.contains("headers.X-Api-Key = \"<redacted>\""),
+        "{list}"
+    );
+    assert!(!list.contains("secret-key"));
+}
+
+#[test]
+fn doctor_dry_run() {
+    let shot = Shot::authed();
+    let out = shot.run(&["doctor", "--dry-run", "-o", "json"]);
+    let report: Value = serde_json::from_slice(&out.stdout).unwrap();
+    let write = report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["name"] == "Images write")
+        .unwrap();
+    assert_eq!(write["status"], "warn", "{report}");
+    assert_eq!(write["detail"], "not checked", "{report}");
+}
+
+#[test]
+fn doctor() {
+    let shot = Shot::authed();
+    let out = shot.run(&["doctor", "-o", "json"]);
+    let report: Value = serde_json::from_slice(&out.stdout).unwrap();
+    let status = |name: &str| {
+        report["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == name)
+            .map(|c| c["status"].as_str().unwrap().to_owned())
+    };
+    for name in [
+        "Auth",
+        "Network",
+        "Token",
+        "Images read",
+        "Images write",
+        "Quota",
+    ] {
+        assert_eq!(status(name).as_deref(), Some("pass"), "{name}: {report}");
+    }
+}
+
+#[test]
+fn upload() {
+    let shot = Shot::authed();
+    let path = shot.image("red.png");
+    let image = shot.json(&[
+        "upload",
+        str(&path),
+        "-o",
+        "json",
+        "--id",
+        "screenshots/red",
+        "-m",
+        "k=v",
+        "-m",
+        "n:=1",
+    ]);
+    assert_eq!(image["id"], "screenshots/red");
+    assert_eq!(image["filename"], "red.png");
+    assert_eq!(image["meta"]["k"], "v");
+    assert_eq!(image["meta"]["n"], 1);
+
+    let stored = shot.mock.blob("screenshots/red").unwrap();
+    assert_eq!(image::load_from_memory(&stored).unwrap().width(), 8);
+}
+
+#[test]
+fn upload_placeholder_off() {
+    let shot = Shot::authed();
+    let path = shot.image("red.png");
+    shot.ok(&["config", "set", "process.placeholder", "true"]);
+    let upload = |args: &[&str]| {
+        let image = shot.json(&[&["upload", str(&path), "-o", "json"], args].concat());
+        image["meta"]["blurhash"].clone()
+    };
+    assert!(upload(&["--id", "a"]).is_string());
+    assert!(upload(&["--id", "b", "--no-placeholder"]).is_null());
+    assert!(upload(&["--id", "c", "--no-placeholder", "--placeholder"]).is_string());
+}
+
+#[test]
+fn upload_original() {
+    let shot = Shot::authed();
+    let path = shot.image("red.png");
+    let image = shot.json(&[
+        "upload",
+        str(&path),
+        "-o",
+        "json",
+        "--format",
+        "original",
+        "-n",
+        "renamed.png",
+    ]);
+    assert_eq!(image["filename"], "renamed.png");
+    let id = image["id"].as_str().unwrap();
+    assert_eq!(shot.mock.blob(id).unwrap(), fs::read(&path).unwrap());
+}
+
+#[test]
+fn upload_stdin() {
+    let shot = Shot::authed();
+    let bytes = fs::read(shot.image("red.png")).unwrap();
+    let mut child = shot
+        .cmd(&["upload", "-", "-o", "json", "--id", "stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&bytes).unwrap();
+    assert!(child.wait_with_output().unwrap().status.success());
+    assert!(shot.mock.blob("stdin").is_some());
+}
+
+#[test]
+fn upload_dry_run() {
+    let shot = Shot::authed();
+    let path = shot.image("red.png");
+    shot.ok(&["--dry-run", "upload", str(&path)]);
+    assert!(shot.mock.images().is_empty());
+}
+
+#[test]
+fn upload_errors() {
+    let shot = Shot::authed();
+    let path = shot.image("red.png");
+    shot.ok(&["upload", str(&path), "--id", "a"]);
+    // Already exists
+    shot.fails(&["upload", str(&path), "--id", "a"], 69);
+
+    shot.mock.fail(Fault {
+        status: 401,
+        times: 1,
+    });
+    shot.fails(&["upload", str(&path)], 77);
+
+    shot.mock.fail(Fault {
+        status: 503,
+        times: 3,
+    });
+    shot.fails(&["upload", str(&path), "--id", "b", "--retries", "1"], 75);
+
+    shot.mock.fail(Fault {
+        status: 413,
+        times: 1,
+    });
+    shot.fails(&["upload", str(&path), "--id", "c"], 69);
+
+    // Retried until it succeeds
+    shot.mock.fail(Fault {
+        status: 500,
+        times: 2,
+    });
+    shot.ok(&["upload", str(&path), "--id", "d"]);
+    let ids = shot
+        .mock
+        .images()
+        .into_iter()
+        .map(|x| x.id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, ["a", "d"]);
+}
+
+#[test]
+fn upload_without_auth() {
+    let shot = Shot::new();
+    let path = shot.image("red.png");
+    shot.fails(&["upload", str(&path)], 77);
+}
+
+#[test]
+fn upload_missing_file() {
+    let shot = Shot::authed();
+    shot.fails(&["upload", "missing.png"], 1);
+}
+
+#[test]
+fn paste_without_clipboard() {
+    if std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        return;
+    }
+    let shot = Shot::authed();
+    shot.fails(&["paste"], 66);
+    assert!(shot.mock.images().is_empty());
+}
+
+#[test]
+fn mock_server() {
+    let shot = Shot::new();
+    let dir = shot.home.path().join("store");
+    let mut server = shot
+        .cmd(&["mock-server", "--addr", "127.0.0.1:0", "--dir", str(&dir)])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(server.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let url = line
+        .split_whitespace()
+        .find(|x| x.starts_with("http://"))
+        .unwrap()
+        .to_owned();
+
+    let path = shot.image("red.png");
+    let upload = shot
+        .cmd(&["upload", str(&path), "--id", "served"])
+        .env("SHOT_API_BASE", &url)
+        .env("SHOT_ACCOUNT_ID", MockServer::ACCOUNT_ID)
+        .env("SHOT_API_TOKEN", MockServer::TOKEN)
+        .output()
+        .unwrap();
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert!(
+        upload.status.success(),
+        "{}",
+        String::from_utf8_lossy(&upload.stderr)
+    );
+    let index = fs::read_to_string(dir.join("images.json")).unwrap();
+    assert!(index.contains("served"));
+}
+
+#[test]
+fn export() {
+    let shot = Shot::authed();
+    let path = shot.image("red.png");
+    shot.ok(&["upload", str(&path), "--id", "dir/a", "-m", "k=v"]);
+    shot.ok(&["upload", str(&path), "--id", "b", "--format", "original"]);
+    let out = shot.home.path().join("export");
+
+    shot.ok(&["export", str(&out), "-j", "2"]);
+    let manifest: Value =
+        serde_json::from_str(&fs::read_to_string(out.join("manifest.json")).unwrap()).unwrap();
+    let images = manifest["images"].as_array().unwrap();
+    assert_eq!(images.len(), 2);
+    assert_eq!(images[0]["id"], "dir/a");
+    assert_eq!(images[0]["meta"]["k"], "v");
+    assert_eq!(images[0]["file"], "images/dir/a.png");
+    for id in ["dir/a", "b"] {
+        let file = out.join("images").join(format!("{id}.png"));
+        assert_eq!(fs::read(file).unwrap(), shot.mock.blob(id).unwrap());
+    }
+
+    // Resumed, re-downloading only changed files
+    let stderr = String::from_utf8(shot.run(&["export", str(&out)]).stderr).unwrap();
+    assert!(stderr.contains("downloaded, 2 already present"), "{stderr}");
+    fs::write(out.join("images/b.png"), "broken").unwrap();
+    let stderr = String::from_utf8(shot.run(&["export", str(&out)]).stderr).unwrap();
+    assert!(stderr.contains("downloaded, 1 already present"), "{stderr}");
+    assert_eq!(
+        fs::read(out.join("images/b.png")).unwrap(),
+        shot.mock.blob("b").unwrap()
+    );
+
+    shot.ok(&["export", str(&out), "--manifest", "ron"]);
+    assert!(out.join("manifest.ron").exists());
+    assert!(!out.join("manifest.json").exists());
+}
+
+#[test]
+fn export_collisions() {
+    let shot = Shot::authed();
+    let red = shot.image("red.png");
+    let blue = shot.home.path().join("blue.png");
+    image::RgbImage::from_pixel(4, 4, image::Rgb([0, 0, 200]))
+        .save(&blue)
+        .unwrap();
+    // All of them would be `images/a.png`, or its parent
+    shot.ok(&["upload", str(&red), "--id", "a"]);
+    shot.ok(&["upload", str(&blue), "--id", "a.png"]);
+    shot.ok(&["upload", str(&blue), "--id", "A.PNG"]);
+    shot.ok(&["upload", str(&red), "--id", "a.png/b"]);
+    let out = shot.home.path().join("export");
+
+    shot.ok(&["export", str(&out)]);
+    let manifest: Value =
+        serde_json::from_str(&fs::read_to_string(out.join("manifest.json")).unwrap()).unwrap();
+    let images = manifest["images"].as_array().unwrap();
+    assert_eq!(images.len(), 4);
+    let mut files = vec![];
+    for image in images {
+        let file = image["file"].as_str().unwrap();
+        let id = image["id"].as_str().unwrap();
+        assert_eq!(
+            fs::read(out.join(file)).unwrap(),
+            shot.mock.blob(id).unwrap()
+        );
+        files.push(file.to_lowercase());
+    }
+    files.sort();
+    files.dedup();
+    assert_eq!(files.len(), 4);
+
+    let stderr = String::from_utf8(shot.run(&["export", str(&out)]).stderr).unwrap();
+    assert!(stderr.contains("downloaded, 4 already present"), "{stderr}");
+}
+
+#[test]
+fn export_variant() {
+    let shot = Shot::authed();
+    let path = shot.image("red.png");
+    shot.ok(&["upload", str(&path), "--id", "a"]);
+    let out = shot.home.path().join("export");
+
+    shot.ok(&["--dry-run", "export", str(&out), "--variant", "public"]);
+    assert!(!out.exists());
+
+    shot.ok(&["export", str(&out), "--variant", "public"]);
+    let manifest = fs::read_to_string(out.join("manifest.json")).unwrap();
+    assert!(manifest.contains("\"variant\": \"public\""));
+    assert!(out.join("images/a.png").exists());
+
+    shot.fails(&["export", str(&out), "--variant", "thumb"], 69);
+}
+
+#[test]
+fn export_failures() {
+    let shot = Shot::authed();
+    let path = shot.image("red.png");
+    shot.ok(&["upload", str(&path), "--id", "a"]);
+    let out = shot.home.path().join("export");
+
+    shot.mock.fail(Fault {
+        status: 401,
+        times: 1,
+    });
+    shot.fails(&["export", str(&out)], 77);
+    shot.ok(&["export", str(&out)]);
+}
+
+#[test]
+fn export_and_import() {
+    let from = Shot::authed();
+    let path = from.image("red.png");
+    from.ok(&["upload", str(&path), "--id", "dir/a", "-m", "k=v"]);
+    from.ok(&["upload", str(&path), "--id", "b", "--format", "original"]);
+    let out = from.home.path().join("export");
+    from.ok(&["export", str(&out), "--manifest", "ron"]);
+
+    let to = Shot::authed();
+    to.ok(&["--dry-run", "import", str(&out.join("manifest.ron"))]);
+    assert!(to.mock.images().is_empty());
+
+    to.ok(&["import", str(&out)]);
+    let images = to.mock.images();
+    assert_eq!(images.len(), 2);
+    for image in from.mock.images() {
+        let imported = images.iter().find(|x| x.id == image.id).unwrap();
+        assert_eq!(imported.filename, image.filename);
+        assert_eq!(imported.meta, image.meta);
+        assert_eq!(to.mock.blob(&image.id), from.mock.blob(&image.id));
+    }
+
+    let stderr = String::from_utf8(to.run(&["import", str(&out)]).stderr).unwrap();
+    assert!(stderr.contains("uploaded, 2 already present"), "{stderr}");
+}
+
+#[test]
+fn import_failures() {
+    let from = Shot::authed();
+    let path = from.image("red.png");
+    from.ok(&["upload", str(&path), "--id", "a"]);
+    from.ok(&["upload", str(&path), "--id", "b"]);
+    let out = from.home.path().join("export");
+    from.ok(&["export", str(&out)]);
+
+    // Changed files are not uploaded, the rest are
+    let to = Shot::authed();
+    fs::write(out.join("images/a.png"), "broken").unwrap();
+    to.fails(&["import", str(&out)], 69);
+    let images = to.mock.images();
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].id, "b");
+
+    to.fails(&["import", str(to.home.path())], 78);
+
+    // Variants are not the originals
+    let variant = from.home.path().join("variant");
+    from.ok(&["export", str(&variant), "--variant", "public"]);
+    let stderr = to.fails(&["import", str(&variant)], 78);
+    assert!(stderr.contains("variant `public`"), "{stderr}");
+
+    // Cloudflare doesn't allow custom IDs of signed images, so nothing is
+    // uploaded
+    let manifest = out.join("manifest.json");
+    let signed = fs::read_to_string(&manifest).unwrap().replacen(
+        r#""requireSignedURLs": false"#,
+        r#""requireSignedURLs": true"#,
+        1,
+    );
+    fs::write(&manifest, signed).unwrap();
+    let to = Shot::authed();
+    let stderr = to.fails(&["import", str(&out)], 78);
+    assert!(stderr.contains("requiring signed URLs"), "{stderr}");
+    assert!(to.mock.images().is_empty());
+}
+
+#[test]
+fn migrate() {
+    let shot = Shot::authed();
+    let path = shot.image("red.png");
+    shot.ok(&["upload", str(&path), "--id", "dir/a", "-m", "k=v"]);
+    shot.ok(&["upload", str(&path), "--id", "b"]);
+    let dir = shot.home.path().join("local");
+    let backend = format!(
+        r#"{{"Local":{{"dir":"{}","base_url":"https://img.example.com/"}}}}"#,
+        str(&dir)
+    );
+    shot.ok(&["config", "set", "profiles.local.backend", &backend]);
+
+    shot.ok(&["--dry-run", "migrate", "--from", "default", "--to", "local"]);
+    assert!(!dir.exists());
+
+    shot.ok(&["migrate", "--from", "default", "--to", "local"]);
+    for id in ["dir/a", "b"] {
+        assert_eq!(fs::read(dir.join(id)).unwrap(), shot.mock.blob(id).unwrap());
+    }
+    let sidecar: Value =
+        serde_json::from_str(&fs::read_to_string(dir.join("dir/a.json")).unwrap()).unwrap();
+    assert_eq!(sidecar["meta"]["k"], "v");
+
+    // Images already in the destination are skipped
+    shot.ok(&["upload", str(&path), "--id", "c"]);
+    let stderr = String::from_utf8(
+        shot.run(&["migrate", "--from", "default", "--to", "local"])
+            .stderr,
+    )
+    .unwrap();
+    assert!(stderr.contains("uploaded, 2 already present"), "{stderr}");
+    assert!(dir.join("c").exists());
+
+    // Without listing, everything is uploaded
+    let upload = shot
+        .mock
+        .url()
+        .join("accounts/mock-account/images/v1")
+        .unwrap();
+    let backend = format!(
+        r#"{{"Custom":{{"url":"{upload}","headers":{{"Authorization":"Bearer {}"}},
+            "result_url":"{{json:result.variants[0]}}"}}}}"#,
+        MockServer::TOKEN
+    );
+    shot.ok(&["config", "set", "profiles.custom.backend", &backend]);
+    let stderr = String::from_utf8(
+        shot.run(&["migrate", "--from", "default", "--to", "custom"])
+            .stderr,
+    )
+    .unwrap();
+    assert!(stderr.contains("can't be skipped"), "{stderr}");
+    assert_eq!(shot.mock.images().len(), 6);
+
+    // Keys of S3 must be IDs to find them again
+    let backend = r#"{"S3":{"endpoint":"http://127.0.0.1:1","bucket":"b","access_key_id":"a",
+        "secret_access_key":"s","key":"x/{id}"}}"#;
+    shot.ok(&["config", "set", "profiles.s3.backend", backend]);
+    let stderr = shot.fails(&["migrate", "--from", "default", "--to", "s3"], 78);
+    assert!(stderr.contains("doesn't keep image IDs"), "{stderr}");
+
+    shot.fails(&["migrate", "--from", "local", "--to", "default"], 78);
+    shot.fails(&["migrate", "--from", "default", "--to", "default"], 1);
+}
+
+#[test]
+fn migrate_env_credentials() {
+    let shot = Shot::new();
+    let auth = r#"{"account_id":"other","token":"other-token"}"#;
+    shot.ok(&["config", "set", "profiles.other.auth", auth]);
+    let backend = format!(
+        r#"{{"Local":{{"dir":"{}","base_url":"https://img.example.com/"}}}}"#,
+        str(&shot.home.path().join("local"))
+    );
+    shot.ok(&["config", "set", "profiles.local.backend", &backend]);
+    let migrate = |flags: &[&str]| {
+        shot.cmd(&[flags, &["migrate", "--from", "other", "--to", "local"]].concat())
+            .env("SHOT_ACCOUNT_ID", MockServer::ACCOUNT_ID)
+            .env("SHOT_API_TOKEN", MockServer::TOKEN)
+            .output()
+            .unwrap()
+    };
+
+    // Only the profile in use takes credentials from env
+    let out = migrate(&["--dry-run"]);
+    assert_eq!(out.status.code(), Some(77));
+    let out = migrate(&["--dry-run", "--profile", "other"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+#[test]
+fn legacy_config() {
+    let shot = Shot::new();
+    let auth = shot.mock.auth();
+    let legacy = format!(
+        r#"(auth: (account_id: "{}", token: "{}"))"#,
+        auth.account_id, auth.token
+    );
+    fs::write(shot.config_path(), &legacy).unwrap();
+
+    // Reading never rewrites it
+    let id = shot.ok(&["config", "get", "profiles.default.auth.account_id"]);
+    assert_eq!(id.trim(), MockServer::ACCOUNT_ID);
+    shot.run(&["--dry-run", "doctor"]);
+    assert_eq!(fs::read_to_string(shot.config_path()).unwrap(), legacy);
+
+    shot.ok(&["config", "set", "profiles.default.variant", "public"]);
+    let config = fs::read_to_string(shot.config_path()).unwrap();
+    assert!(config.contains("profiles"), "{config}");
+    assert!(config.contains(MockServer::ACCOUNT_ID));
+}
+
+#[test]
+fn project_config_paths() {
+    let shot = Shot::authed();
+    let path = shot.image("a.png");
+    shot.image("mark.png");
+    fs::write(
+        shot.home.path().join(".shot.ron"),
+        r#"(process: (watermark: Some("mark.png")))"#,
+    )
+    .unwrap();
+    let sub = shot.home.path().join("sub");
+    fs::create_dir(&sub).unwrap();
+
+    let out = shot
+        .cmd(&["upload", str(&path)])
+        .current_dir(&sub)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}